@@ -1,11 +1,842 @@
-pub struct APU {}
+use crate::memory_map::*;
+
+pub const SAMPLE_RATE: u32 = 48000;
+const CPU_CLOCK: u32 = 4_194_304;
+// The frame sequencer runs at 512 Hz
+const FRAME_SEQUENCER_PERIOD: u32 = CPU_CLOCK / 512;
+// Each of the four channels can reach this amplitude, so the full mix fits in an i16
+const CHANNEL_AMPLITUDE: f32 = 8191.0;
+
+const REGISTERS_SIZE: usize = NR51 - NR10 + 1;
+const WAVE_RAM_SIZE: usize = WPR_END - WPR_START + 1;
+
+// Bits that always read 1 for each register from NR10 to the end of Wave Pattern RAM
+const READ_MASKS: [u8; WPR_END - NR10 + 1] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // Unused, NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // Unused, NR41-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // Unused
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Wave Pattern RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+struct LengthCounter {
+    counter: u16,
+    max: u16,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    fn new(max: u16) -> LengthCounter {
+        LengthCounter {
+            counter: 0,
+            max,
+            enabled: false,
+        }
+    }
+
+    fn load(&mut self, value: u8) {
+        self.counter = self.max - value as u16;
+    }
+
+    fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    // Returns true when the counter runs out and the channel has to be disabled
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        return false;
+    }
+}
+
+struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    fn write(&mut self, value: u8) {
+        self.initial_volume = value >> 4;
+        self.increase = value & 0b0000_1000 != 0;
+        self.period = value & 0b0000_0111;
+    }
+
+    // The DAC is only powered while the upper 5 bits of NRx2 are not all 0
+    fn dac_enabled(&self) -> bool {
+        return self.initial_volume != 0 || self.increase;
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+struct SquareChannel {
+    enabled: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    frequency_timer: u16,
+    // Sweep, only present on channel 1
+    has_sweep: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_timer: u8,
+    sweep_enabled: bool,
+    shadow_frequency: u16,
+}
+
+impl SquareChannel {
+    fn new(has_sweep: bool) -> SquareChannel {
+        SquareChannel {
+            enabled: false,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            frequency_timer: 0,
+            has_sweep,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_timer: 0,
+            sweep_enabled: false,
+            shadow_frequency: 0,
+        }
+    }
+
+    fn write_sweep(&mut self, value: u8) {
+        self.sweep_period = (value >> 4) & 0b0000_0111;
+        self.sweep_negate = value & 0b0000_1000 != 0;
+        self.sweep_shift = value & 0b0000_0111;
+    }
+
+    fn write_length_duty(&mut self, value: u8) {
+        self.duty = value >> 6;
+        self.length.load(value & 0b0011_1111);
+    }
+
+    fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    fn write_frequency_low(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x0700) | value as u16;
+    }
+
+    fn write_frequency_high(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x00FF) | (((value & 0b0000_0111) as u16) << 8);
+        self.length.enabled = value & 0b0100_0000 != 0;
+        if value & 0b1000_0000 != 0 {
+            self.trigger();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.frequency_timer = (2048 - self.frequency) * 4;
+        self.envelope.trigger();
+
+        if self.has_sweep {
+            self.shadow_frequency = self.frequency;
+            self.sweep_timer = if self.sweep_period == 0 {
+                8
+            } else {
+                self.sweep_period
+            };
+            self.sweep_enabled = self.sweep_period != 0 || self.sweep_shift != 0;
+            if self.sweep_shift != 0 {
+                self.sweep_frequency();
+            }
+        }
+    }
+
+    // Computes the next sweep frequency, disabling the channel on overflow
+    fn sweep_frequency(&mut self) -> u16 {
+        let delta = self.shadow_frequency >> self.sweep_shift;
+        let frequency = if self.sweep_negate {
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        };
+        if frequency > 2047 {
+            self.enabled = false;
+        }
+        return frequency;
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_timer > 0 {
+            self.sweep_timer -= 1;
+        }
+        if self.sweep_timer != 0 {
+            return;
+        }
+        self.sweep_timer = if self.sweep_period == 0 {
+            8
+        } else {
+            self.sweep_period
+        };
+        if self.sweep_enabled && self.sweep_period != 0 {
+            let frequency = self.sweep_frequency();
+            if frequency <= 2047 && self.sweep_shift != 0 {
+                self.frequency = frequency;
+                self.shadow_frequency = frequency;
+                // The new frequency is checked for overflow again, but not written
+                self.sweep_frequency();
+            }
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn step(&mut self) {
+        if self.frequency_timer > 0 {
+            self.frequency_timer -= 1;
+        }
+        if self.frequency_timer == 0 {
+            self.frequency_timer = (2048 - self.frequency) * 4;
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+    }
+
+    fn output(&self) -> Option<u8> {
+        if !self.envelope.dac_enabled() {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+        return Some(
+            DUTY_PATTERNS[self.duty as usize][self.duty_step as usize] * self.envelope.volume,
+        );
+    }
+}
+
+struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    length: LengthCounter,
+    volume_code: u8,
+    frequency: u16,
+    frequency_timer: u16,
+    position: u8,
+    sample: u8,
+    wave_ram: [u8; WAVE_RAM_SIZE],
+}
+
+impl WaveChannel {
+    fn new() -> WaveChannel {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            length: LengthCounter::new(256),
+            volume_code: 0,
+            frequency: 0,
+            frequency_timer: 0,
+            position: 0,
+            sample: 0,
+            wave_ram: [0; WAVE_RAM_SIZE],
+        }
+    }
+
+    fn write_dac(&mut self, value: u8) {
+        self.dac_enabled = value & 0b1000_0000 != 0;
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    fn write_volume(&mut self, value: u8) {
+        self.volume_code = (value >> 5) & 0b0000_0011;
+    }
+
+    fn write_frequency_low(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x0700) | value as u16;
+    }
+
+    fn write_frequency_high(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x00FF) | (((value & 0b0000_0111) as u16) << 8);
+        self.length.enabled = value & 0b0100_0000 != 0;
+        if value & 0b1000_0000 != 0 {
+            self.trigger();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger();
+        self.frequency_timer = (2048 - self.frequency) * 2;
+        self.position = 0;
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn step(&mut self) {
+        if self.frequency_timer > 0 {
+            self.frequency_timer -= 1;
+        }
+        if self.frequency_timer == 0 {
+            self.frequency_timer = (2048 - self.frequency) * 2;
+            self.position = (self.position + 1) % 32;
+            let byte = self.wave_ram[(self.position / 2) as usize];
+            // The upper nibble is played first
+            self.sample = if self.position % 2 == 0 {
+                byte >> 4
+            } else {
+                byte & 0x0F
+            };
+        }
+    }
+
+    fn output(&self) -> Option<u8> {
+        if !self.dac_enabled {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+        return Some(match self.volume_code {
+            0 => 0,
+            1 => self.sample,
+            2 => self.sample >> 1,
+            _ => self.sample >> 2,
+        });
+    }
+}
+
+struct NoiseChannel {
+    enabled: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    clock_shift: u8,
+    width_mode: bool,
+    divisor_code: u8,
+    frequency_timer: u32,
+    lfsr: u16,
+}
+
+impl NoiseChannel {
+    fn new() -> NoiseChannel {
+        NoiseChannel {
+            enabled: false,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            clock_shift: 0,
+            width_mode: false,
+            divisor_code: 0,
+            frequency_timer: 0,
+            lfsr: 0x7FFF,
+        }
+    }
+
+    fn write_length(&mut self, value: u8) {
+        self.length.load(value & 0b0011_1111);
+    }
+
+    fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    fn write_polynomial(&mut self, value: u8) {
+        self.clock_shift = value >> 4;
+        self.width_mode = value & 0b0000_1000 != 0;
+        self.divisor_code = value & 0b0000_0111;
+    }
+
+    fn write_control(&mut self, value: u8) {
+        self.length.enabled = value & 0b0100_0000 != 0;
+        if value & 0b1000_0000 != 0 {
+            self.trigger();
+        }
+    }
+
+    fn period(&self) -> u32 {
+        return NOISE_DIVISORS[self.divisor_code as usize] << self.clock_shift;
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.frequency_timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn step(&mut self) {
+        if self.frequency_timer > 0 {
+            self.frequency_timer -= 1;
+        }
+        if self.frequency_timer == 0 {
+            self.frequency_timer = self.period();
+            let xor = (self.lfsr & 0b01) ^ ((self.lfsr & 0b10) >> 1);
+            self.lfsr = (self.lfsr >> 1) | (xor << 14);
+            // In 7-bit mode the result is also stored in bit 6
+            if self.width_mode {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (xor << 6);
+            }
+        }
+    }
+
+    fn output(&self) -> Option<u8> {
+        if !self.envelope.dac_enabled() {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+        return Some((!self.lfsr & 0b01) as u8 * self.envelope.volume);
+    }
+}
+
+// Turns the analog output of the channels into stereo samples
+struct Mixer {
+    // The output capacitors that remove the DC offset, for each channel and side
+    capacitors: [[f32; 2]; 4],
+    capacitor_charge: f32,
+}
+
+impl Mixer {
+    fn new() -> Mixer {
+        Mixer {
+            capacitors: [[0.0; 2]; 4],
+            capacitor_charge: 0.999958_f32.powf((CPU_CLOCK / SAMPLE_RATE) as f32),
+        }
+    }
+
+    // Returns the stereo sample of every channel on its own, they add up to the full mix
+    fn mix(&mut self, analog: &[f32; 4], panning: u8, master_volume: u8) -> [[f32; 2]; 4] {
+        let volumes = [
+            (((master_volume >> 4) & 0b0111) + 1) as f32 / 8.0,
+            ((master_volume & 0b0111) + 1) as f32 / 8.0,
+        ];
+        let mut samples = [[0.0; 2]; 4];
+        for channel in 0..4 {
+            // NR51 has the right side on the low nibble and the left one on the high nibble
+            let sides = [
+                panning & (0b0001_0000 << channel) != 0,
+                panning & (0b0000_0001 << channel) != 0,
+            ];
+            for side in 0..2 {
+                let input = if sides[side] {
+                    analog[channel] * volumes[side]
+                } else {
+                    0.0
+                };
+                let output = input - self.capacitors[channel][side];
+                self.capacitors[channel][side] = input - output * self.capacitor_charge;
+                samples[channel][side] = output * CHANNEL_AMPLITUDE;
+            }
+        }
+        return samples;
+    }
+}
+
+// Samples produced at exactly SAMPLE_RATE for recording, the mix and every channel interleaved
+#[derive(Default)]
+pub struct CapturedAudio {
+    pub mix: Vec<i16>,
+    pub channels: [Vec<i16>; 4],
+}
+
+struct Capture {
+    sample_dots: u32,
+    mixer: Mixer,
+    audio: CapturedAudio,
+}
+
+pub struct APU {
+    powered: bool,
+    registers: [u8; REGISTERS_SIZE],
+    channel1: SquareChannel,
+    channel2: SquareChannel,
+    channel3: WaveChannel,
+    channel4: NoiseChannel,
+    frame_sequencer_dots: u32,
+    frame_sequencer_step: u8,
+    sample_dots: u32,
+    // Samples produced per second of emulated time, nudged to keep the audio buffer level
+    output_rate: u32,
+    mixer: Mixer,
+    muted: [bool; 4],
+    solo: Option<usize>,
+    capture: Option<Capture>,
+    // Interleaved stereo samples waiting to be played
+    samples: Vec<i16>,
+}
 
 impl APU {
     pub fn new() -> APU {
-        APU {}
+        // Values left by the boot ROM, which powers the APU on
+        let mut registers = [0; REGISTERS_SIZE];
+        registers[NR50 - NR10] = 0x77;
+        registers[NR51 - NR10] = 0xF3;
+        APU {
+            powered: true,
+            registers,
+            channel1: SquareChannel::new(true),
+            channel2: SquareChannel::new(false),
+            channel3: WaveChannel::new(),
+            channel4: NoiseChannel::new(),
+            frame_sequencer_dots: 0,
+            frame_sequencer_step: 0,
+            sample_dots: 0,
+            output_rate: SAMPLE_RATE,
+            mixer: Mixer::new(),
+            muted: [false; 4],
+            solo: None,
+            capture: None,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            if self.powered {
+                self.step_dot();
+            }
+
+            // Samples are produced even when powered off, the output is just silent
+            self.sample_dots += self.output_rate;
+            if self.sample_dots >= CPU_CLOCK {
+                self.sample_dots -= CPU_CLOCK;
+                self.push_sample();
+            }
+
+            // Recordings don't follow the rate control, so they always have the right pitch
+            let capture_due = match &mut self.capture {
+                Some(capture) => {
+                    capture.sample_dots += SAMPLE_RATE;
+                    capture.sample_dots >= CPU_CLOCK
+                }
+                None => false,
+            };
+            if capture_due {
+                self.capture_sample();
+            }
+        }
+    }
+
+    pub fn set_output_rate(&mut self, rate: u32) {
+        self.output_rate = rate;
+    }
+
+    // Hands over the samples produced since the last call
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        return std::mem::replace(&mut self.samples, Vec::new());
+    }
+
+    pub fn start_capture(&mut self) {
+        self.capture = Some(Capture {
+            sample_dots: 0,
+            mixer: Mixer::new(),
+            audio: CapturedAudio::default(),
+        });
+    }
+
+    pub fn stop_capture(&mut self) {
+        self.capture = None;
+    }
+
+    // Hands over the samples captured since the last call
+    pub fn take_captured_audio(&mut self) -> Option<CapturedAudio> {
+        return match &mut self.capture {
+            Some(capture) => Some(std::mem::replace(
+                &mut capture.audio,
+                CapturedAudio::default(),
+            )),
+            None => None,
+        };
+    }
+
+    pub fn toggle_mute(&mut self, channel: usize) {
+        self.muted[channel] = !self.muted[channel];
+    }
+
+    pub fn toggle_solo(&mut self, channel: usize) {
+        self.solo = if self.solo == Some(channel) {
+            None
+        } else {
+            Some(channel)
+        };
+    }
+
+    // Whether the channel makes it to the mix, after applying mutes and solo
+    pub fn channel_audible(&self, channel: usize) -> bool {
+        return match self.solo {
+            Some(solo) => solo == channel,
+            None => !self.muted[channel],
+        };
+    }
+
+    fn step_dot(&mut self) {
+        self.frame_sequencer_dots += 1;
+        if self.frame_sequencer_dots == FRAME_SEQUENCER_PERIOD {
+            self.frame_sequencer_dots = 0;
+            self.clock_frame_sequencer();
+        }
+
+        self.channel1.step();
+        self.channel2.step();
+        self.channel3.step();
+        self.channel4.step();
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        // Lengths are clocked on even steps, sweep on 2 and 6, envelopes on 7
+        if self.frame_sequencer_step % 2 == 0 {
+            self.channel1.clock_length();
+            self.channel2.clock_length();
+            self.channel3.clock_length();
+            self.channel4.clock_length();
+        }
+        if self.frame_sequencer_step == 2 || self.frame_sequencer_step == 6 {
+            self.channel1.clock_sweep();
+        }
+        if self.frame_sequencer_step == 7 {
+            self.channel1.envelope.clock();
+            self.channel2.envelope.clock();
+            self.channel4.envelope.clock();
+        }
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) % 8;
+    }
+
+    // Analog output of every channel, between -1 and 1
+    fn channel_outputs(&self) -> [f32; 4] {
+        let mut analog = [0.0; 4];
+        if !self.powered {
+            return analog;
+        }
+        let outputs = [
+            self.channel1.output(),
+            self.channel2.output(),
+            self.channel3.output(),
+            self.channel4.output(),
+        ];
+        for (channel, output) in outputs.iter().enumerate() {
+            // A disabled DAC outputs nothing, an enabled one maps 0-15 to 1 to -1
+            if let Some(value) = output {
+                analog[channel] = 1.0 - *value as f32 / 7.5;
+            }
+        }
+        return analog;
+    }
+
+    // Adds up the audible channels into one stereo sample
+    fn mix_audible(&self, channel_samples: &[[f32; 2]; 4]) -> [i16; 2] {
+        let mut mix = [0.0; 2];
+        for channel in 0..4 {
+            if self.channel_audible(channel) {
+                mix[0] += channel_samples[channel][0];
+                mix[1] += channel_samples[channel][1];
+            }
+        }
+        return [mix[0] as i16, mix[1] as i16];
+    }
+
+    fn push_sample(&mut self) {
+        let analog = self.channel_outputs();
+        let channel_samples = self.mixer.mix(
+            &analog,
+            self.registers[NR51 - NR10],
+            self.registers[NR50 - NR10],
+        );
+        let mix = self.mix_audible(&channel_samples);
+        self.samples.extend_from_slice(&mix);
+    }
+
+    fn capture_sample(&mut self) {
+        let analog = self.channel_outputs();
+        let panning = self.registers[NR51 - NR10];
+        let master_volume = self.registers[NR50 - NR10];
+        let channel_samples = match &mut self.capture {
+            Some(capture) => {
+                capture.sample_dots -= CPU_CLOCK;
+                capture.mixer.mix(&analog, panning, master_volume)
+            }
+            None => return,
+        };
+        let mix = self.mix_audible(&channel_samples);
+        if let Some(capture) = &mut self.capture {
+            capture.audio.mix.extend_from_slice(&mix);
+            // Every channel is captured even when muted, so it can be listened to on its own
+            for channel in 0..4 {
+                let samples = &mut capture.audio.channels[channel];
+                samples.push(channel_samples[channel][0] as i16);
+                samples.push(channel_samples[channel][1] as i16);
+            }
+        }
+    }
+
+    fn channel_status(&self) -> u8 {
+        let mut status = 0;
+        if self.channel1.enabled {
+            status |= 0b0001;
+        }
+        if self.channel2.enabled {
+            status |= 0b0010;
+        }
+        if self.channel3.enabled {
+            status |= 0b0100;
+        }
+        if self.channel4.enabled {
+            status |= 0b1000;
+        }
+        return status;
+    }
+
+    pub fn read_register(&self, address: usize) -> u8 {
+        let value = match address {
+            NR52 => {
+                let power = if self.powered { 0b1000_0000 } else { 0 };
+                power | self.channel_status()
+            }
+            WPR_START..=WPR_END => self.channel3.wave_ram[address - WPR_START],
+            NR10..=NR51 => self.registers[address - NR10],
+            _ => 0,
+        };
+        return value | READ_MASKS[address - NR10];
+    }
+
+    pub fn write_register(&mut self, address: usize, value: u8) {
+        match address {
+            // Wave RAM and NR52 are reachable even with the APU powered off
+            WPR_START..=WPR_END => self.channel3.wave_ram[address - WPR_START] = value,
+            NR52 => self.set_power(value & 0b1000_0000 != 0),
+            NR10..=NR51 => {
+                if !self.powered {
+                    // The DMG still lets the length counters be loaded
+                    match address {
+                        NR11 => self.channel1.length.load(value & 0b0011_1111),
+                        NR21 => self.channel2.length.load(value & 0b0011_1111),
+                        NR31 => self.channel3.length.load(value),
+                        NR41 => self.channel4.write_length(value),
+                        _ => {}
+                    }
+                    return;
+                }
+                self.registers[address - NR10] = value;
+                self.write_channel_register(address, value);
+            }
+            _ => {} // Unused
+        }
+    }
+
+    fn write_channel_register(&mut self, address: usize, value: u8) {
+        match address {
+            NR10 => self.channel1.write_sweep(value),
+            NR11 => self.channel1.write_length_duty(value),
+            NR12 => self.channel1.write_envelope(value),
+            NR13 => self.channel1.write_frequency_low(value),
+            NR14 => self.channel1.write_frequency_high(value),
+
+            NR21 => self.channel2.write_length_duty(value),
+            NR22 => self.channel2.write_envelope(value),
+            NR23 => self.channel2.write_frequency_low(value),
+            NR24 => self.channel2.write_frequency_high(value),
+
+            NR30 => self.channel3.write_dac(value),
+            NR31 => self.channel3.length.load(value),
+            NR32 => self.channel3.write_volume(value),
+            NR33 => self.channel3.write_frequency_low(value),
+            NR34 => self.channel3.write_frequency_high(value),
+
+            NR41 => self.channel4.write_length(value),
+            NR42 => self.channel4.write_envelope(value),
+            NR43 => self.channel4.write_polynomial(value),
+            NR44 => self.channel4.write_control(value),
+
+            _ => {} // NR50, NR51 and the unused registers only need to be stored
+        }
     }
 
-    pub fn do_nothing(&self) {
-        println!("Sound not implemented yet");
+    fn set_power(&mut self, on: bool) {
+        if on && !self.powered {
+            self.frame_sequencer_dots = 0;
+            self.frame_sequencer_step = 0;
+        } else if !on && self.powered {
+            // Powering off clears every register, but not Wave RAM
+            let wave_ram = self.channel3.wave_ram;
+            self.registers = [0; REGISTERS_SIZE];
+            self.channel1 = SquareChannel::new(true);
+            self.channel2 = SquareChannel::new(false);
+            self.channel3 = WaveChannel::new();
+            self.channel3.wave_ram = wave_ram;
+            self.channel4 = NoiseChannel::new();
+        }
+        self.powered = on;
     }
 }
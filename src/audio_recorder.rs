@@ -0,0 +1,120 @@
+use crate::apu::{CapturedAudio, SAMPLE_RATE};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+const HEADER_SIZE: u32 = 44;
+
+// 16-bit stereo PCM WAV file, the sizes in the header are filled in when it's finished
+struct WavWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    data_size: u32,
+}
+
+impl WavWriter {
+    fn create(path: PathBuf) -> io::Result<WavWriter> {
+        let mut file = BufWriter::new(File::create(&path)?);
+        let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+        file.write_all(b"RIFF")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&CHANNELS.to_le_bytes())?;
+        file.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        file.write_all(&(SAMPLE_RATE * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        return Ok(WavWriter {
+            path,
+            file,
+            data_size: 0,
+        });
+    }
+
+    fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_size += (samples.len() * 2) as u32;
+        return Ok(());
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file
+            .write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_size.to_le_bytes())?;
+        self.file.flush()?;
+        return Ok(());
+    }
+}
+
+// Writes the APU output to WAV files, either the mix or each channel on its own file
+pub struct AudioRecorder {
+    writers: Vec<WavWriter>,
+    per_channel: bool,
+}
+
+impl AudioRecorder {
+    // Creates the files in the folder, recording per channel appends the channel number to the name
+    pub fn start(folder: &Path, name: &str, per_channel: bool) -> io::Result<AudioRecorder> {
+        let mut writers = Vec::new();
+        if per_channel {
+            for channel in 1..=4 {
+                writers.push(WavWriter::create(
+                    folder.join(format!("{}-ch{}.wav", name, channel)),
+                )?);
+            }
+        } else {
+            writers.push(WavWriter::create(folder.join(format!("{}.wav", name)))?);
+        }
+        for writer in writers.iter() {
+            println!("Recording audio to {:?}", writer.path);
+        }
+        return Ok(AudioRecorder {
+            writers,
+            per_channel,
+        });
+    }
+
+    pub fn per_channel(&self) -> bool {
+        return self.per_channel;
+    }
+
+    pub fn write(&mut self, audio: &CapturedAudio) {
+        let result = if self.per_channel {
+            self.writers
+                .iter_mut()
+                .zip(audio.channels.iter())
+                .map(|(writer, samples)| writer.write_samples(samples))
+                .collect()
+        } else {
+            self.writers[0].write_samples(&audio.mix)
+        };
+        if let Err(error) = result {
+            eprintln!("Couldn't write the audio recording: {}", error);
+        }
+    }
+
+    pub fn finish(mut self) {
+        for writer in self.writers.iter_mut() {
+            match writer.finish() {
+                Ok(()) => println!("Saved the audio recording {:?}", writer.path),
+                Err(error) => eprintln!(
+                    "Couldn't finish the audio recording {:?}: {}",
+                    writer.path, error
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+extern crate sfml;
+
+use crate::apu::SAMPLE_RATE;
+use sfml::audio::SoundStream;
+use sfml::system::Time;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+// Stereo frames handed to SFML on every request, about 20 ms
+const CHUNK_FRAMES: usize = 1024;
+// Latency the rate control aims for, two chunks
+const TARGET_BUFFERED_SAMPLES: usize = CHUNK_FRAMES * 2 * 2;
+// The sample rate is nudged by at most 0.5%, which can't be heard
+const MAX_RATE_DEVIATION: f64 = 0.005;
+// Samples beyond this are dropped so the latency can't keep growing
+const MAX_BUFFERED_SAMPLES: usize = (SAMPLE_RATE / 5 * 2) as usize;
+
+// Feeds the samples produced by the APU to SFML, which pulls them from its own thread
+pub struct AudioStream {
+    buffer: Arc<Mutex<VecDeque<i16>>>,
+    chunk: Vec<i16>,
+    last_sample: [i16; 2],
+}
+
+impl AudioStream {
+    pub fn new() -> AudioStream {
+        AudioStream {
+            buffer: Arc::new(Mutex::new(VecDeque::new())),
+            chunk: vec![0; CHUNK_FRAMES * 2],
+            last_sample: [0; 2],
+        }
+    }
+
+    // Handle for the emulation thread to queue samples
+    pub fn buffer(&self) -> Arc<Mutex<VecDeque<i16>>> {
+        return self.buffer.clone();
+    }
+
+    pub fn queue_samples(buffer: &Mutex<VecDeque<i16>>, samples: &[i16]) {
+        let mut buffer = buffer.lock().unwrap();
+        buffer.extend(samples);
+        while buffer.len() > MAX_BUFFERED_SAMPLES {
+            buffer.pop_front();
+        }
+    }
+
+    // Dynamic rate control: produce fewer samples when the buffer fills up and more when it runs low
+    pub fn adjusted_sample_rate(buffer: &Mutex<VecDeque<i16>>) -> u32 {
+        let queued = buffer.lock().unwrap().len() as f64;
+        let target = TARGET_BUFFERED_SAMPLES as f64;
+        let deviation = ((target - queued) / target).max(-1.0).min(1.0) * MAX_RATE_DEVIATION;
+        return (SAMPLE_RATE as f64 * (1.0 + deviation)) as u32;
+    }
+}
+
+impl SoundStream for AudioStream {
+    fn get_data(&mut self) -> (&mut [i16], bool) {
+        let mut buffer = self.buffer.lock().unwrap();
+        for frame in self.chunk.chunks_mut(2) {
+            // On an underrun the last sample is held, which avoids clicks
+            if buffer.len() >= 2 {
+                self.last_sample = [buffer.pop_front().unwrap(), buffer.pop_front().unwrap()];
+            }
+            frame.copy_from_slice(&self.last_sample);
+        }
+        return (&mut self.chunk, true);
+    }
+
+    fn seek(&mut self, _offset: Time) {}
+
+    fn channel_count(&self) -> u32 {
+        return 2;
+    }
+
+    fn sample_rate(&self) -> u32 {
+        return SAMPLE_RATE;
+    }
+}
@@ -3,12 +3,30 @@ use std::fs;
 use std::io;
 use std::io::Write;
 use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::thread;
 
 pub fn read_any_key() {
     let mut scanned_line = String::new();
     io::stdin().read_line(&mut scanned_line).unwrap();
 }
 
+// Reads lines from stdin on another thread, so the emulation doesn't block waiting for them
+pub fn spawn_command_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut scanned_line = String::new();
+        while let Ok(nbytes) = io::stdin().read_line(&mut scanned_line) {
+            if nbytes == 0 || sender.send(scanned_line.trim().to_string()).is_err() {
+                break;
+            }
+            scanned_line.clear();
+        }
+    });
+    return receiver;
+}
+
 pub fn choose_rom(settings: &config::Config) -> PathBuf {
     let roms_folder = fs::read_dir(settings.get_str("roms_folder").unwrap()).unwrap();
     let roms_number = roms_folder.count();
@@ -6,7 +6,7 @@ extern crate bit_field;
 extern crate hex;
 use crate::apu::APU;
 use crate::gpu::GPU;
-use crate::interrupt_controller::InterruptController;
+use crate::interrupt_controller::{InterruptController, InterruptFlags};
 use crate::joypad::*;
 use crate::link_cable::LinkCable;
 use crate::memory_map::*;
@@ -21,16 +21,15 @@ pub struct CPU {
     pub mmu: MMU,
     pub interrupt_controller: InterruptController,
     pub gpu: GPU,
-    link_cable: LinkCable,
+    pub link_cable: LinkCable,
     pub joypad: Joypad,
     pub apu: APU,
     pub timer: Timer,
     max_pc: u16,
     pub cb_prefix: bool,
     pub halted: bool,
-}
-pub enum MBCType {
-    MBC0,
+    pub stopped: bool,
+    halt_bug: bool,
 }
 impl CPU {
     pub fn new() -> CPU {
@@ -46,14 +45,8 @@ impl CPU {
             max_pc: 0,
             cb_prefix: false,
             halted: false,
-        }
-    }
-
-    pub fn swap_mbc(mbc_type: MBCType) {
-        match mbc_type {
-            MBCType::MBC0 => {
-                println!("Not swapping the MBC");
-            }
+            stopped: false,
+            halt_bug: false,
         }
     }
 
@@ -61,20 +54,98 @@ impl CPU {
         self.mmu.open_rom(rom_path);
     }
 
+    pub fn open_gbs(&mut self, gbs_path: PathBuf) {
+        self.mmu.open_gbs(gbs_path);
+    }
+
     pub fn do_cycle(&mut self) -> u8 {
+        let cycles = self.step();
+        // The OAM DMA runs alongside the CPU, copying one byte per M-cycle
+        for _ in 0..cycles {
+            if let Some((source, offset)) = self.mmu.step_dma() {
+                let value = match source as usize {
+                    VRAM_START..=VRAM_END => self.gpu.read_byte_vram(source as usize - VRAM_START),
+                    _ => self.mmu.read_byte(source),
+                };
+                self.gpu.write_byte_oam(offset, value);
+            }
+        }
+        return cycles;
+    }
+
+    fn step(&mut self) -> u8 {
+        // While halted the CPU doesn't fetch anything, but the rest of the hardware keeps running
+        if self.halted {
+            self.wake_from_halt();
+            return 1;
+        }
         // if self.registers.pc > self.max_pc {
         //     self.max_pc = self.registers.pc;
         //     self.print_registers();
         // }
+        if self.stopped {
+            // STOP only ends when a button is pressed
+            if !self.joypad.any_key_pressed() {
+                return 1;
+            }
+            self.stopped = false;
+        }
+        // Interrupts are only serviced between instructions, never after a CB prefix
+        if !self.cb_prefix {
+            let interrupt_cycles = self.handle_interrupts();
+            if interrupt_cycles > 0 {
+                return interrupt_cycles;
+            }
+        }
+        // EI only takes effect after the instruction that follows it
+        let ime_delay_over = self.interrupt_controller.ime_scheduled();
         let instruction: u8 = self.read_byte(self.registers.pc);
-        self.registers.pc += 1;
+        if self.halt_bug {
+            // The PC fails to increment, so the next byte is read twice
+            self.halt_bug = false;
+        } else {
+            self.registers.pc += 1;
+        }
         let cycles = self.execute_instruction(instruction);
+        if ime_delay_over && !self.cb_prefix && self.interrupt_controller.ime_scheduled() {
+            self.interrupt_controller.enable_master_interrupt();
+        }
         // if cycles == 0 {
         //     panic!("should never gotten here...");
         // }
         return cycles;
     }
 
+    // Jumps to the vector of the highest priority interrupt, returns the cycles spent
+    fn handle_interrupts(&mut self) -> u8 {
+        if !self.interrupt_controller.ime() || !self.interrupt_controller.interrupt_pending() {
+            return 0;
+        }
+        let interrupt = self.interrupt_controller.get_first_interrupt();
+        let vector: u16 = match interrupt {
+            InterruptFlags::VBlank => 0x0040,
+            InterruptFlags::LCDStat => 0x0048,
+            InterruptFlags::Timer => 0x0050,
+            InterruptFlags::Serial => 0x0058,
+            InterruptFlags::Joypad => 0x0060,
+            InterruptFlags::None => return 0,
+        };
+        self.interrupt_controller.disable_master_interrupt();
+        self.interrupt_controller.clear_interrupt_flag(interrupt);
+        // The handler's first opcode must be fetched normally
+        self.halt_bug = false;
+        self.push_to_stack(self.registers.pc);
+        self.jump_to(vector);
+        5
+    }
+
+    // HALT ends as soon as an enabled interrupt is pending, even if IME is off
+    fn wake_from_halt(&mut self) {
+        if self.interrupt_controller.interrupt_pending() {
+            self.halted = false;
+        }
+    }
+
     pub fn print_registers(&self) {
         println!("{:#4X?}", self.registers);
     }
@@ -100,22 +171,32 @@ impl CPU {
                 0x00..=0x07 => self.rlc_opcode(instruction),
                 // RRC Reg
                 0x08..=0x0F => self.rrc_opcode(instruction),
+                // RL Reg
+                0x10..=0x17 => self.rl_opcode(instruction),
+                // RR Reg
+                0x18..=0x1F => self.rr_opcode(instruction),
                 // SLA Reg
-                0x20..=0x2F => self.sla_opcode(instruction),
+                0x20..=0x27 => self.sla_opcode(instruction),
+                // SRA Reg
+                0x28..=0x2F => self.sra_opcode(instruction),
                 // Swap Reg
                 0x30..=0x37 => self.swap_opcode(instruction),
+                // SRL Reg
+                0x38..=0x3F => self.srl_opcode(instruction),
+                // BIT n, Reg
                 0x40..=0x7F => {
                     let bit = parse_destination_register(instruction, 0x04);
                     self.bit_opcode(instruction, bit)
                 }
+                // RES n, Reg
                 0x80..=0xBF => {
                     let bit = parse_destination_register(instruction, 0x08);
                     self.res_opcode(instruction, bit)
                 }
-                _ => {
-                    //self.print_registers();
-                    handle_unimplemented_instruction(instruction, true);
-                    0
+                // SET n, Reg
+                0xC0..=0xFF => {
+                    let bit = parse_destination_register(instruction, 0x0C);
+                    self.set_opcode(instruction, bit)
                 }
             }
         } else {
@@ -200,9 +281,12 @@ impl CPU {
                     self.registers.a = self.alu_rrca(self.registers.a);
                     1
                 }
-                // STOP TODO
+                // STOP
                 0x10 => {
-                    (handle_unimplemented_instruction(instruction, false));
+                    // STOP is encoded as 0x10 0x00, the second byte is ignored
+                    self.fetch_byte();
+                    self.timer.set_div(0);
+                    self.stopped = true;
                     1
                 }
                 // LD DE, d16
@@ -319,9 +403,9 @@ impl CPU {
                     self.registers.h = self.fetch_byte();
                     2
                 }
-                // DAA TODO
+                // DAA
                 0x27 => {
-                    (handle_unimplemented_instruction(instruction, false));
+                    self.alu_daa();
                     1
                 }
                 // JR Z, r8
@@ -408,8 +492,11 @@ impl CPU {
                     self.write_byte(self.registers.hl(), immediate);
                     3
                 }
-                // SCF TODO
-                0x37 => handle_unimplemented_instruction(instruction, false),
+                // SCF
+                0x37 => {
+                    self.alu_scf();
+                    1
+                }
                 // JR C, r8
                 0x38 => {
                     let took_jump = self.jr_if_flag(CpuFlags::C);
@@ -454,9 +541,17 @@ impl CPU {
                 }
                 // Register Movements
                 0x40..=0x75 => self.register_movement(instruction),
-                // HALT TODO
+                // HALT
                 0x76 => {
-                    self.halted = true;
+                    // An EI right before counts as IME already set, so EI; HALT never triggers the bug
+                    let ime = self.interrupt_controller.ime()
+                        || self.interrupt_controller.ime_scheduled();
+                    if !ime && self.interrupt_controller.interrupt_pending() {
+                        // HALT bug: the CPU doesn't halt and the next opcode byte is read twice
+                        self.halt_bug = true;
+                    } else {
+                        self.halted = true;
+                    }
                     1
                 }
                 // More Register Movements
@@ -679,7 +774,7 @@ impl CPU {
                 }
                 // EI
                 0xFB => {
-                    self.interrupt_controller.enable_master_interrupt();
+                    self.interrupt_controller.schedule_master_interrupt();
                     1
                 }
                 // CP, d8
@@ -835,12 +930,28 @@ impl CPU {
         return return_value;
     }
 
+    fn set_opcode(&mut self, opcode: u8, bit: u8) -> u8 {
+        let operand_cycles = self.get_operand_and_cycles(opcode);
+        let operand = operand_cycles.0;
+        let mut return_value = operand_cycles.1;
+
+        let value = operand | (1 << bit);
+        return_value = return_value + self.write_to_register(opcode, value);
+        return return_value;
+    }
+
     fn swap_opcode(&mut self, opcode: u8) -> u8 {
         let operand_cycles = self.get_operand_and_cycles(opcode);
         let operand = operand_cycles.0;
         let mut return_value = operand_cycles.1;
 
         let swap_value = ((operand & 0xf0) >> 4) | ((operand & 0x0f) << 4);
+
+        self.registers.set_flags(CpuFlags::Z, swap_value == 0);
+        self.registers.set_flags(CpuFlags::N, false);
+        self.registers.set_flags(CpuFlags::H, false);
+        self.registers.set_flags(CpuFlags::C, false);
+
         return_value = return_value + self.write_to_register(opcode, swap_value);
 
         return return_value;
@@ -864,6 +975,70 @@ impl CPU {
         return return_value;
     }
 
+    fn sra_opcode(&mut self, opcode: u8) -> u8 {
+        let operand_cycles = self.get_operand_and_cycles(opcode);
+        let operand = operand_cycles.0;
+        let mut return_value = operand_cycles.1;
+
+        // Arithmetic shift, bit 7 keeps its value
+        let r_value = (operand >> 1) | (operand & 0x80);
+
+        self.registers.set_flags(CpuFlags::Z, r_value == 0);
+        self.registers.set_flags(CpuFlags::N, false);
+        self.registers.set_flags(CpuFlags::H, false);
+        self.registers
+            .set_flags(CpuFlags::C, operand & 0x01 == 0x01);
+
+        return_value = return_value + self.write_to_register(opcode, r_value);
+
+        return return_value;
+    }
+
+    fn srl_opcode(&mut self, opcode: u8) -> u8 {
+        let operand_cycles = self.get_operand_and_cycles(opcode);
+        let operand = operand_cycles.0;
+        let mut return_value = operand_cycles.1;
+
+        let r_value = operand >> 1;
+
+        self.registers.set_flags(CpuFlags::Z, r_value == 0);
+        self.registers.set_flags(CpuFlags::N, false);
+        self.registers.set_flags(CpuFlags::H, false);
+        self.registers
+            .set_flags(CpuFlags::C, operand & 0x01 == 0x01);
+
+        return_value = return_value + self.write_to_register(opcode, r_value);
+
+        return return_value;
+    }
+
+    fn rl_opcode(&mut self, opcode: u8) -> u8 {
+        let operand_cycles = self.get_operand_and_cycles(opcode);
+        let operand = operand_cycles.0;
+        let mut return_value = operand_cycles.1;
+
+        // The CB variant always sets Z from the result
+        let r_value = self.alu_rla(operand);
+        self.registers.set_flags(CpuFlags::Z, r_value == 0);
+
+        return_value = return_value + self.write_to_register(opcode, r_value);
+
+        return return_value;
+    }
+
+    fn rr_opcode(&mut self, opcode: u8) -> u8 {
+        let operand_cycles = self.get_operand_and_cycles(opcode);
+        let operand = operand_cycles.0;
+        let mut return_value = operand_cycles.1;
+
+        let r_value = self.alu_rra(operand);
+        self.registers.set_flags(CpuFlags::Z, r_value == 0);
+
+        return_value = return_value + self.write_to_register(opcode, r_value);
+
+        return return_value;
+    }
+
     fn rlc_opcode(&mut self, opcode: u8) -> u8 {
         let operand_cycles = self.get_operand_and_cycles(opcode);
         let operand = operand_cycles.0;
@@ -1087,6 +1262,42 @@ impl CPU {
             .set_flags(CpuFlags::C, !self.registers.get_flag(CpuFlags::C));
     }
 
+    fn alu_scf(&mut self) {
+        self.registers.set_flags(CpuFlags::N, false);
+        self.registers.set_flags(CpuFlags::H, false);
+        self.registers.set_flags(CpuFlags::C, true);
+    }
+
+    fn alu_daa(&mut self) {
+        let mut a = self.registers.a;
+        let mut carry = self.registers.get_flag(CpuFlags::C);
+        let half_carry = self.registers.get_flag(CpuFlags::H);
+
+        if !self.registers.get_flag(CpuFlags::N) {
+            // After an addition, adjust if there was a carry or the nibbles went over 9
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if half_carry || (a & 0x0F) > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        } else {
+            // After a subtraction, only the carries tell us what to undo
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+            if half_carry {
+                a = a.wrapping_sub(0x06);
+            }
+        }
+
+        self.registers.a = a;
+        self.registers.set_flags(CpuFlags::Z, a == 0);
+        self.registers.set_flags(CpuFlags::H, false);
+        self.registers.set_flags(CpuFlags::C, carry);
+    }
+
     fn alu_cpl(&mut self) {
         self.registers.a = !self.registers.a;
 
@@ -1229,6 +1440,10 @@ impl CPU {
     }
 
     fn write_byte(&mut self, address: u16, value: u8) {
+        // During OAM DMA the CPU can only reach HRAM and the IO registers
+        if self.mmu.dma_transfer() && (address as usize) < IO_START {
+            return;
+        }
         match address as usize {
             VRAM_START..=VRAM_END => self
                 .gpu
@@ -1238,25 +1453,33 @@ impl CPU {
             UNUSED_AREA_START..=UNUSED_AREA_END => {} // Do nothing
 
             JOYP => self.joypad.set_joyp(value),
+            SB => self.link_cable.set_sb(value),
+            SC => self.link_cable.set_sc(value),
 
+            DIV => self.timer.set_div(value),
+            TIMA => self.timer.set_tima(value),
             TMA => self.timer.set_tma(value),
+            TAC => self.timer.set_tac(value),
+
+            IF => self.interrupt_controller.set_iflag(value),
+
+            NR10..=WPR_END => self.apu.write_register(address as usize, value),
 
-            NR10..=WPR_START => self.apu.do_nothing(),
             LCDC => self.gpu.set_lcdc(value),
             STAT => self.gpu.set_stat(value),
             SCY => self.gpu.set_scy(value),
             SCX => self.gpu.set_scx(value),
+            LY => {} // Read only
             LYC => self.gpu.set_lyc(value),
-            WY => self.gpu.set_wy(value),
-            WX => self.gpu.set_wx(value),
             DMA => self.mmu.start_dma(value),
             BGP => self.gpu.set_bgp(value),
             OBP0 => self.gpu.set_obp0(value),
             OBP1 => self.gpu.set_obp1(value),
-            SB => self.link_cable.set_sb(value),
-            SC => self.link_cable.set_sc(value),
+            WY => self.gpu.set_wy(value),
+            WX => self.gpu.set_wx(value),
+
+            IO_START..=IO_END => {} // Unmapped IO
 
-            IF => self.interrupt_controller.set_iflag(value),
             IE => self.interrupt_controller.set_ie(value),
             _ => self.mmu.write_byte(address, value),
         }
@@ -1268,14 +1491,48 @@ impl CPU {
     }
 
     fn read_byte(&self, address: u16) -> u8 {
+        // During OAM DMA the CPU can only reach HRAM and the IO registers, OAM included reads $FF
+        if self.mmu.dma_transfer() && (address as usize) < IO_START {
+            return 0xFF;
+        }
         match address as usize {
             VRAM_START..=VRAM_END => return self.gpu.read_byte_vram(address as usize - VRAM_START),
             OAM_START..=OAM_END => return self.gpu.read_byte_oam(address as usize - OAM_START),
-            UNUSED_AREA_START..=UNUSED_AREA_END => return 0xFF, // Default bus read
+            // The DMG reads $FF while the PPU holds OAM and $00 otherwise
+            UNUSED_AREA_START..=UNUSED_AREA_END => {
+                if self.gpu.oam_blocked() {
+                    return 0xFF;
+                }
+                return 0x00;
+            }
             JOYP => self.joypad.joyp(),
+            SB => self.link_cable.sb(),
+            SC => self.link_cable.sc(),
+
+            DIV => self.timer.div(),
+            TIMA => self.timer.tima(),
+            TMA => self.timer.tma(),
+            TAC => self.timer.tac(),
+
+            IF => self.interrupt_controller.iflag(),
+
+            NR10..=WPR_END => self.apu.read_register(address as usize),
+
             LCDC => self.gpu.lcdc(),
             STAT => self.gpu.stat(),
+            SCY => self.gpu.scy(),
+            SCX => self.gpu.scx(),
             LY => self.gpu.ly(),
+            LYC => self.gpu.lyc(),
+            DMA => self.mmu.dma_register(),
+            BGP => self.gpu.bgp(),
+            OBP0 => self.gpu.obp0(),
+            OBP1 => self.gpu.obp1(),
+            WY => self.gpu.wy(),
+            WX => self.gpu.wx(),
+
+            IO_START..=IO_END => 0xFF, // Unmapped IO
+
             IE => self.interrupt_controller.ie(),
             _ => return self.mmu.read_byte(address),
         }
@@ -1351,3 +1608,44 @@ fn handle_unimplemented_instruction(opcode: u8, prefixed: bool) -> u8 {
     );
     return 0;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Loads the program in WRAM with a VBlank interrupt enabled and already requested
+    fn cpu_with_pending_interrupt(program: &[u8]) -> CPU {
+        let mut cpu = CPU::new();
+        for (offset, byte) in program.iter().enumerate() {
+            cpu.write_byte(RAM_START as u16 + offset as u16, *byte);
+        }
+        cpu.registers.pc = RAM_START as u16;
+        cpu.write_byte(IE as u16, 0b0000_0001);
+        cpu.write_byte(IF as u16, 0b0000_0001);
+        return cpu;
+    }
+
+    #[test]
+    fn ei_halt_dispatches_without_halt_bug() {
+        // EI; HALT; NOP
+        let mut cpu = cpu_with_pending_interrupt(&[0xFB, 0x76, 0x00]);
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(cpu.registers.pc, 0x0040);
+        assert!(!cpu.halt_bug);
+        assert_eq!(cpu.read_word(cpu.registers.sp), RAM_START as u16 + 2);
+    }
+
+    #[test]
+    fn di_halt_reads_the_next_byte_twice() {
+        // DI; HALT; INC A
+        let mut cpu = cpu_with_pending_interrupt(&[0xF3, 0x76, 0x3C]);
+        let a = cpu.registers.a;
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(cpu.registers.a, a.wrapping_add(2));
+        assert_eq!(cpu.registers.pc, RAM_START as u16 + 3);
+    }
+}
@@ -3,24 +3,47 @@ extern crate sfml;
 const WINDOW_WIDTH: u32 = 160;
 const WINDOW_HEIGTH: u32 = 144;
 const PIXEL_SCALE: u32 = 3;
-const CYCLES_PER_FRAME: u32 = 69905;
+const CYCLES_PER_FRAME: u32 = 70224;
+// 70224 dots at 4.194304 MHz, about 59.73 frames per second
+const FRAME_DURATION: Duration = Duration::from_nanos(16_742_706);
+// Lagging further behind than this drops the time instead of trying to catch up
+const MAX_FRAMES_BEHIND: u32 = 5;
+// The last stretch before a frame deadline is waited without sleeping, which is too coarse
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+const RUMBLE_OFFSET: f32 = PIXEL_SCALE as f32;
+// Battery RAM gets flushed every 10 seconds, in case the emulator doesn't exit cleanly
+const SAVE_INTERVAL_FRAMES: u32 = 600;
+use crate::audio_recorder::AudioRecorder;
+use crate::audio_stream::AudioStream;
 use crate::cli;
 use crate::cpu::CPU;
+use crate::gbs;
+use crate::gbs::GbsHeader;
 use crate::interrupt_controller::InterruptFlags;
 use crate::joypad::KeyValue;
+use crate::printer::Printer;
+use crate::tcp_link::TcpLink;
+use sfml::audio::SoundStreamPlayer;
 use sfml::graphics::{Color, RenderTarget, RenderWindow, Sprite, Texture, Transformable};
 use sfml::system::Vector2f;
-use sfml::window::{Event, Style};
+use sfml::window::{Event, Key, Style};
+use std::fs;
 use std::path::PathBuf;
 use std::thread::sleep;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 pub struct Device {
     cpu: CPU,
+    rom_path: PathBuf,
+    audio_recorder: Option<AudioRecorder>,
 }
 
 impl Device {
     pub fn new() -> Device {
-        Device { cpu: CPU::new() }
+        Device {
+            cpu: CPU::new(),
+            rom_path: PathBuf::new(),
+            audio_recorder: None,
+        }
     }
     pub fn run(&mut self) -> () {
         let mut debug_mode: bool = false;
@@ -34,90 +57,81 @@ impl Device {
         );
         window.set_active(true);
         let mut texture = Texture::new(WINDOW_WIDTH, WINDOW_HEIGTH).unwrap();
+        let mut rumble_frame: bool = false;
+        let mut frames_since_save: u32 = 0;
+        let mut audio_stream = AudioStream::new();
+        let audio_buffer = audio_stream.buffer();
+        let mut audio_player = SoundStreamPlayer::new(&mut audio_stream);
+        audio_player.play();
+        let mut next_frame = Instant::now();
+        let mut total_cycles: u32 = 0;
         loop {
-            let now = Instant::now();
-            let mut total_cycles: u32 = 0;
             while total_cycles < CYCLES_PER_FRAME {
                 let cycles_elapsed = self.cpu.do_cycle() * 4;
                 total_cycles += cycles_elapsed as u32;
 
-                if !self.cpu.cb_prefix {
-                    if self.cpu.mmu.dma_transfer {
-                        for i in 0..0xA0 {
-                            self.cpu.gpu.write_byte_oam(
-                                i as usize,
-                                self.cpu.mmu.read_byte(self.cpu.mmu.dma_address),
-                            );
-                        }
-                        self.cpu.mmu.dma_transfer = false;
-                    }
-                    self.cpu.gpu.update_scanlines(cycles_elapsed);
+                self.cpu.gpu.update(cycles_elapsed);
+                self.cpu.timer.update_timer(cycles_elapsed as u16);
+                self.cpu.apu.update(cycles_elapsed);
+                self.cpu.link_cable.update(cycles_elapsed);
 
-                    if self.cpu.gpu.stat_interrupt_req {
-                        self.cpu
-                            .interrupt_controller
-                            .set_interrupt_flag(InterruptFlags::LCDStat);
-                        self.cpu.gpu.stat_interrupt_req = false;
-                    }
+                if self.cpu.gpu.stat_interrupt_req {
+                    self.cpu
+                        .interrupt_controller
+                        .set_interrupt_flag(InterruptFlags::LCDStat);
+                    self.cpu.gpu.stat_interrupt_req = false;
+                }
 
-                    if self.cpu.gpu.vblank_interrupt_req {
-                        self.cpu
-                            .interrupt_controller
-                            .set_interrupt_flag(InterruptFlags::VBlank);
-                        self.cpu.gpu.vblank_interrupt_req = false;
-                    }
+                if self.cpu.gpu.vblank_interrupt_req {
+                    self.cpu
+                        .interrupt_controller
+                        .set_interrupt_flag(InterruptFlags::VBlank);
+                    self.cpu.gpu.vblank_interrupt_req = false;
+                }
 
-                    if self.cpu.joypad.joypad_interrupt_req {
-                        self.cpu
-                            .interrupt_controller
-                            .set_interrupt_flag(InterruptFlags::Joypad);
-                        self.cpu.joypad.joypad_interrupt_req = false;
-                    }
+                if self.cpu.timer.timer_interrupt_req {
+                    self.cpu
+                        .interrupt_controller
+                        .set_interrupt_flag(InterruptFlags::Timer);
+                    self.cpu.timer.timer_interrupt_req = false;
+                }
 
-                    if self.cpu.interrupt_controller.ime() {
-                        self.cpu.interrupt_controller.disable_master_interrupt();
-                        let interrupt = self.cpu.interrupt_controller.get_first_interrupt();
-                        match interrupt {
-                            InterruptFlags::VBlank => {
-                                self.cpu.push_to_stack(self.cpu.registers.pc);
-                                self.cpu.registers.pc = 0x0040;
-                                self.cpu
-                                    .interrupt_controller
-                                    .clear_interrupt_flag(InterruptFlags::VBlank);
-                                total_cycles += 5;
-                            }
-                            InterruptFlags::LCDStat => {
-                                self.cpu.push_to_stack(self.cpu.registers.pc);
-                                self.cpu.registers.pc = 0x0048;
-                                self.cpu
-                                    .interrupt_controller
-                                    .clear_interrupt_flag(InterruptFlags::LCDStat);
-                                total_cycles += 5;
-                            }
-                            InterruptFlags::Joypad => {
-                                self.cpu.push_to_stack(self.cpu.registers.pc);
-                                self.cpu.registers.pc = 0x0060;
-                                self.cpu
-                                    .interrupt_controller
-                                    .clear_interrupt_flag(InterruptFlags::Joypad);
-                                total_cycles += 5;
-                            }
-                            _ => self.cpu.interrupt_controller.enable_master_interrupt(),
-                        }
-                    }
+                if self.cpu.link_cable.serial_interrupt_req {
+                    self.cpu
+                        .interrupt_controller
+                        .set_interrupt_flag(InterruptFlags::Serial);
+                    self.cpu.link_cable.serial_interrupt_req = false;
+                }
 
-                    if final_debug == true {
-                        self.cpu.print_registers();
-                        cli::read_any_key();
-                    }
+                if self.cpu.joypad.joypad_interrupt_req {
+                    self.cpu
+                        .interrupt_controller
+                        .set_interrupt_flag(InterruptFlags::Joypad);
+                    self.cpu.joypad.joypad_interrupt_req = false;
+                }
 
-                    if self.cpu.registers.pc == 0x0040 {
-                        final_debug = false;
-                    }
+                if final_debug == true {
+                    self.cpu.print_registers();
+                    cli::read_any_key();
+                }
 
-                    if second_debug == true && self.cpu.registers.pc == 0x0040 {
-                        final_debug = true;
-                    }
+                if self.cpu.registers.pc == 0x0040 {
+                    final_debug = false;
+                }
+
+                if second_debug == true && self.cpu.registers.pc == 0x0040 {
+                    final_debug = true;
+                }
+            }
+            // Instructions overshoot the frame by a few dots, they count towards the next one
+            total_cycles -= CYCLES_PER_FRAME;
+            self.cpu
+                .apu
+                .set_output_rate(AudioStream::adjusted_sample_rate(&audio_buffer));
+            AudioStream::queue_samples(&audio_buffer, &self.cpu.apu.take_samples());
+            if let Some(recorder) = &mut self.audio_recorder {
+                if let Some(audio) = self.cpu.apu.take_captured_audio() {
+                    recorder.write(&audio);
                 }
             }
             // User input
@@ -128,6 +142,11 @@ impl Device {
                         code: sfml::window::Key::Escape,
                         ..
                     } => {
+                        self.cpu.mmu.save_ram();
+                        self.stop_audio_recording();
+                        // Exiting skips destructors, so a printer gets to save its pending paper here
+                        self.cpu.link_cable.detach();
+                        audio_player.stop();
                         window.close();
                         std::process::exit(0)
                     }
@@ -196,6 +215,25 @@ impl Device {
                         ..
                     } => self.cpu.joypad.set_key_released(KeyValue::Select),
 
+                    Event::KeyPressed {
+                        code: sfml::window::Key::F9,
+                        ..
+                    } => self.toggle_audio_recording(false),
+                    Event::KeyPressed {
+                        code: sfml::window::Key::F10,
+                        ..
+                    } => self.toggle_audio_recording(true),
+                    // Number keys mute an audio channel, or solo it while holding shift
+                    Event::KeyPressed { code, shift, .. } if audio_channel_key(code).is_some() => {
+                        let channel = audio_channel_key(code).unwrap();
+                        if shift {
+                            self.cpu.apu.toggle_solo(channel);
+                        } else {
+                            self.cpu.apu.toggle_mute(channel);
+                        }
+                        self.print_audio_channels();
+                    }
+
                     _ => {}
                 }
             }
@@ -211,23 +249,239 @@ impl Device {
             }
             let mut background_sprite = Sprite::with_texture(&texture);
             background_sprite.set_scale(Vector2f::new(PIXEL_SCALE as f32, PIXEL_SCALE as f32));
+            // There's no motor to drive, so rumble shakes the picture instead
+            if self.cpu.mmu.rumble_active() {
+                rumble_frame = !rumble_frame;
+                let offset = if rumble_frame {
+                    RUMBLE_OFFSET
+                } else {
+                    -RUMBLE_OFFSET
+                };
+                background_sprite.set_position(Vector2f::new(offset, 0.0));
+            }
             window.clear(Color::BLACK);
             window.draw(&background_sprite);
             window.display();
-            let time_spent = now.elapsed().as_millis();
-            if time_spent < 16 {
-                let time_to_sleep = 16 - time_spent;
-                //eprintln!("Going to sleep for {} miliseconds", time_to_sleep);
-                sleep(Duration::from_millis(time_to_sleep as u64));
-            } else {
-                eprintln!(
-                    "Falling behind... last frame took {} miliseconds",
-                    time_spent
-                );
+            frames_since_save += 1;
+            if frames_since_save >= SAVE_INTERVAL_FRAMES {
+                self.cpu.mmu.save_ram();
+                frames_since_save = 0;
             }
+            pace_frame(&mut next_frame);
         }
     }
+
+    // Plays a GBS music rip without any display, the tracks are changed from the terminal
+    pub fn play_gbs(&mut self, gbs_path: PathBuf) {
+        let header = gbs::read_header(&gbs_path);
+        println!(
+            "Playing {:?} by {:?}, {:?}",
+            header.title, header.author, header.copyright
+        );
+        println!("Write n for the next track, p for the previous one or q to quit");
+        let commands = cli::spawn_command_reader();
+        let mut audio_stream = AudioStream::new();
+        let audio_buffer = audio_stream.buffer();
+        let mut audio_player = SoundStreamPlayer::new(&mut audio_stream);
+        audio_player.play();
+
+        let song_count = header.song_count;
+        // Songs are numbered from 1 in the header, but the init routine counts from 0
+        let mut song = header.first_song - 1;
+        self.start_gbs_song(&gbs_path, &header, song);
+        let mut next_frame = Instant::now();
+        let mut total_cycles: u32 = 0;
+        loop {
+            while total_cycles < CYCLES_PER_FRAME {
+                let cycles_elapsed = self.cpu.do_cycle() * 4;
+                total_cycles += cycles_elapsed as u32;
+
+                self.cpu.timer.update_timer(cycles_elapsed as u16);
+                self.cpu.apu.update(cycles_elapsed);
+
+                if self.cpu.timer.timer_interrupt_req {
+                    self.cpu
+                        .interrupt_controller
+                        .set_interrupt_flag(InterruptFlags::Timer);
+                    self.cpu.timer.timer_interrupt_req = false;
+                }
+            }
+            total_cycles -= CYCLES_PER_FRAME;
+            // There's no PPU running, so VBlank is raised once per frame here
+            self.cpu
+                .interrupt_controller
+                .set_interrupt_flag(InterruptFlags::VBlank);
+            self.cpu
+                .apu
+                .set_output_rate(AudioStream::adjusted_sample_rate(&audio_buffer));
+            AudioStream::queue_samples(&audio_buffer, &self.cpu.apu.take_samples());
+
+            if let Ok(command) = commands.try_recv() {
+                match command.as_str() {
+                    "n" => {
+                        song = (song + 1) % song_count;
+                        self.start_gbs_song(&gbs_path, &header, song);
+                    }
+                    "p" => {
+                        song = if song == 0 { song_count - 1 } else { song - 1 };
+                        self.start_gbs_song(&gbs_path, &header, song);
+                    }
+                    "q" => {
+                        audio_player.stop();
+                        return;
+                    }
+                    _ => println!("Unknown command {:?}", command),
+                }
+            }
+            pace_frame(&mut next_frame);
+        }
+    }
+
+    // Resets the hardware and calls the init routine of the song, the play routine runs from interrupts
+    fn start_gbs_song(&mut self, gbs_path: &PathBuf, header: &GbsHeader, song: u8) {
+        self.cpu = CPU::new();
+        self.cpu.open_gbs(gbs_path.clone());
+        self.cpu.registers.a = song;
+        self.cpu.registers.sp = header.stack_pointer;
+        self.cpu.registers.pc = gbs::DRIVER_ADDRESS;
+        self.cpu.timer.set_tma(header.timer_modulo);
+        self.cpu.timer.set_tima(header.timer_modulo);
+        self.cpu.timer.set_tac(header.timer_control);
+        println!("Track {} of {}", song + 1, header.song_count);
+    }
+
     pub fn open_rom(&mut self, rom_path: PathBuf) {
+        self.rom_path = rom_path.clone();
         self.cpu.open_rom(rom_path);
     }
+
+    pub fn set_serial_echo(&mut self, echo: bool) {
+        self.cpu.link_cable.set_echo_to_stdout(echo);
+    }
+
+    // Links with another gmbr process, the listening side waits here until the other one connects
+    pub fn link_over_tcp(&mut self, address: &str, listen: bool) {
+        let link = if listen {
+            TcpLink::listen(address)
+        } else {
+            TcpLink::connect(address)
+        };
+        match link {
+            Ok(link) => self.cpu.link_cable.attach(Box::new(link)),
+            Err(error) => eprintln!("Couldn't link with {}: {}", address, error),
+        }
+    }
+
+    // Plugs a Game Boy Printer into the link port, the pages are saved in the folder
+    pub fn attach_printer(&mut self, output_folder: PathBuf) {
+        if let Err(error) = fs::create_dir_all(&output_folder) {
+            eprintln!(
+                "Couldn't create the printer folder {:?}: {}",
+                output_folder, error
+            );
+            return;
+        }
+        self.cpu
+            .link_cable
+            .attach(Box::new(Printer::new(output_folder)));
+    }
+
+    // Records the mixed output, or every channel on its own file, next to the ROM
+    pub fn start_audio_recording(&mut self, per_channel: bool) {
+        self.stop_audio_recording();
+        let folder = self
+            .rom_path
+            .parent()
+            .unwrap_or(&self.rom_path)
+            .to_path_buf();
+        let stem = self
+            .rom_path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        let name = format!("{}-{}", stem, timestamp);
+        match AudioRecorder::start(&folder, &name, per_channel) {
+            Ok(recorder) => {
+                self.cpu.apu.start_capture();
+                self.audio_recorder = Some(recorder);
+            }
+            Err(error) => eprintln!("Couldn't start the audio recording: {}", error),
+        }
+    }
+
+    fn stop_audio_recording(&mut self) {
+        if let Some(mut recorder) = self.audio_recorder.take() {
+            if let Some(audio) = self.cpu.apu.take_captured_audio() {
+                recorder.write(&audio);
+            }
+            self.cpu.apu.stop_capture();
+            recorder.finish();
+        }
+    }
+
+    // Stops the recording in progress, or starts one if it was recording the other way
+    fn toggle_audio_recording(&mut self, per_channel: bool) {
+        let same_recording = match &self.audio_recorder {
+            Some(recorder) => recorder.per_channel() == per_channel,
+            None => false,
+        };
+        if same_recording {
+            self.stop_audio_recording();
+        } else {
+            self.start_audio_recording(per_channel);
+        }
+    }
+
+    fn print_audio_channels(&self) {
+        let states: Vec<String> = (0..4)
+            .map(|channel| {
+                let state = if self.cpu.apu.channel_audible(channel) {
+                    "on"
+                } else {
+                    "off"
+                };
+                format!("{}: {}", channel + 1, state)
+            })
+            .collect();
+        println!("Audio channels {}", states.join(", "));
+    }
+}
+
+fn audio_channel_key(key: Key) -> Option<usize> {
+    return match key {
+        Key::Num1 => Some(0),
+        Key::Num2 => Some(1),
+        Key::Num3 => Some(2),
+        Key::Num4 => Some(3),
+        _ => None,
+    };
+}
+
+// Waits for the next frame, or gives up on the lost time when too far behind
+fn pace_frame(next_frame: &mut Instant) {
+    *next_frame += FRAME_DURATION;
+    let now = Instant::now();
+    if now < *next_frame {
+        wait_until(*next_frame);
+    } else if now - *next_frame > FRAME_DURATION * MAX_FRAMES_BEHIND {
+        eprintln!(
+            "Falling behind... skipping {} miliseconds",
+            (now - *next_frame).as_millis()
+        );
+        *next_frame = now;
+    }
+}
+
+fn wait_until(deadline: Instant) {
+    let now = Instant::now();
+    if deadline > now + SPIN_MARGIN {
+        sleep(deadline - now - SPIN_MARGIN);
+    }
+    while Instant::now() < deadline {
+        std::thread::yield_now();
+    }
 }
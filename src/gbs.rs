@@ -0,0 +1,203 @@
+use crate::mbc;
+use crate::mbc::{BANK0_END, BANK0_START, BANKN_END, BANKN_START, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use crate::memory_map::*;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::str;
+
+const HEADER_SIZE: usize = 0x70;
+// The player code lives where a cartridge has its entry point, the rip is loaded after it
+pub const DRIVER_ADDRESS: u16 = 0x0100;
+const VBLANK_VECTOR: usize = 0x0040;
+const TIMER_VECTOR: usize = 0x0050;
+const MIN_LOAD_ADDRESS: u16 = 0x0400;
+
+pub struct GbsHeader {
+    pub song_count: u8,
+    pub first_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+impl GbsHeader {
+    fn parse(header: &[u8]) -> GbsHeader {
+        assert_eq!(&header[0x00..0x03], b"GBS", "This is not a GBS file!");
+        let word = |offset: usize| (header[offset] as u16) | ((header[offset + 1] as u16) << 8);
+        let text = |offset: usize| {
+            let mut text = str::from_utf8(&header[offset..offset + 0x20])
+                .unwrap_or("")
+                .to_string();
+            text.retain(|c| c != '\0');
+            text
+        };
+        let song_count = header[0x04];
+        assert!(song_count > 0, "The GBS file has no songs");
+        let gbs_header = GbsHeader {
+            song_count,
+            // Songs are numbered from 1, an out of range first song starts at the closest one
+            first_song: header[0x05].max(1).min(song_count),
+            load_address: word(0x06),
+            init_address: word(0x08),
+            play_address: word(0x0A),
+            stack_pointer: word(0x0C),
+            timer_modulo: header[0x0E],
+            timer_control: header[0x0F],
+            title: text(0x10),
+            author: text(0x30),
+            copyright: text(0x50),
+        };
+        assert!(
+            gbs_header.load_address >= MIN_LOAD_ADDRESS,
+            "The GBS load address {:#06X} overlaps the player code",
+            gbs_header.load_address
+        );
+        return gbs_header;
+    }
+
+    // The play routine runs on the timer interrupt if the rip sets the timer up, VBlank otherwise
+    pub fn uses_timer(&self) -> bool {
+        return self.timer_control & 0b0000_0100 != 0;
+    }
+}
+
+pub fn read_header(gbs_path: &PathBuf) -> GbsHeader {
+    let mut gbs_file = File::open(gbs_path).expect("Error opening the GBS file");
+    let mut header = [0; HEADER_SIZE];
+    gbs_file
+        .read_exact(&mut header)
+        .expect("The GBS file is too small to contain a header. Closing the emulator...");
+    return GbsHeader::parse(&header);
+}
+
+// Minimal ROM-only cartridge around a GBS rip, with MBC1-style bank switching and 8KB of RAM
+pub struct GBS {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank: usize,
+}
+
+impl mbc::MBC for GBS {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address as usize {
+            BANK0_START..=BANK0_END => return self.rom[address as usize],
+            BANKN_START..=BANKN_END => {
+                let offset = self.rom_bank * ROM_BANK_SIZE + (address as usize - BANKN_START);
+                return self.rom[offset % self.rom.len()];
+            }
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => {
+                return self.ram[address as usize - EXTERNAL_RAM_START]
+            }
+            _ => return 0xFF,
+        }
+    }
+    fn read_word(&self, address: u16) -> u16 {
+        return (self.read_byte(address) as u16) | ((self.read_byte(address + 1) as u16) << 8);
+    }
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address as usize {
+            // ROM bank number, 0 maps to 1 like on MBC1
+            0x2000..=0x3FFF => self.rom_bank = (value as usize).max(1),
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => {
+                self.ram[address as usize - EXTERNAL_RAM_START] = value
+            }
+            _ => {}
+        }
+    }
+    fn write_word(&mut self, address: u16, value: u16) {
+        self.write_byte(address, value as u8);
+        self.write_byte(address + 1, (value >> 8) as u8);
+    }
+
+    fn open_rom(&mut self, gbs_path: PathBuf) {
+        let mut gbs: Vec<u8> = Vec::new();
+        let mut gbs_file = File::open(&gbs_path).expect("Error opening the GBS file");
+        gbs_file.read_to_end(&mut gbs).unwrap();
+        assert!(
+            gbs.len() >= HEADER_SIZE,
+            "The GBS file is too small to contain a header. Closing the emulator..."
+        );
+        let header = GbsHeader::parse(&gbs[..HEADER_SIZE]);
+        let data = &gbs[HEADER_SIZE..];
+
+        // The image is rounded up to whole banks, with at least the two banks of a 32KB cartridge
+        let load_address = header.load_address as usize;
+        let banks = ((load_address + data.len() + ROM_BANK_SIZE - 1) / ROM_BANK_SIZE).max(2);
+        self.rom = vec![0xFF; banks * ROM_BANK_SIZE];
+        self.rom[load_address..load_address + data.len()].copy_from_slice(data);
+        write_player_code(&mut self.rom, &header);
+        println!("The file is a GBS rip, so it's supported. Continuing...");
+    }
+
+    // GBS rips have no battery
+    fn save_ram(&mut self) {}
+}
+
+impl GBS {
+    pub fn new() -> GBS {
+        GBS {
+            rom: Vec::new(),
+            ram: vec![0; RAM_BANK_SIZE],
+            rom_bank: 1,
+        }
+    }
+}
+
+// Fills the space before the rip with the code that calls its routines
+fn write_player_code(rom: &mut [u8], header: &GbsHeader) {
+    let load = header.load_address;
+    let init = header.init_address;
+    let play = header.play_address;
+
+    // RST vectors jump to the rip's own handlers at the load address
+    for vector in (0x00..=0x38).step_by(8) {
+        let target = load + vector as u16;
+        rom[vector..vector + 3].copy_from_slice(&[0xC3, target as u8, (target >> 8) as u8]);
+    }
+
+    // CALL play; RETI
+    let play_handler = [0xCD, play as u8, (play >> 8) as u8, 0xD9];
+    let vector = if header.uses_timer() {
+        TIMER_VECTOR
+    } else {
+        VBLANK_VECTOR
+    };
+    rom[vector..vector + play_handler.len()].copy_from_slice(&play_handler);
+
+    // The song number is passed in A, the rest of the setup is done before jumping here
+    let interrupt_enable = if header.uses_timer() {
+        0b0000_0100
+    } else {
+        0b0000_0001
+    };
+    let driver = [
+        // CALL init
+        0xCD,
+        init as u8,
+        (init >> 8) as u8,
+        // LD A, IE; LDH (IE), A
+        0x3E,
+        interrupt_enable,
+        0xE0,
+        (IE & 0xFF) as u8,
+        // XOR A; LDH (IF), A, so nothing raised during init is pending when halting
+        0xAF,
+        0xE0,
+        (IF & 0xFF) as u8,
+        // EI
+        0xFB,
+        // HALT; JR -3
+        0x76,
+        0x18,
+        0xFD,
+    ];
+    let start = DRIVER_ADDRESS as usize;
+    rom[start..start + driver.len()].copy_from_slice(&driver);
+}
@@ -1,15 +1,24 @@
 extern crate bit_field;
 use bit_field::BitField;
+use std::collections::VecDeque;
 
 const VRAM_SIZE: usize = 0x2000;
 const OAM_SIZE: usize = 0xA0;
 const LYC_INTERRUPT_ENABLED: u8 = 0b0100_0000;
 const OAM_INTERRUPT_ENABLED: u8 = 0b0010_0000;
-const VBLANK_INTERRUPT_ENABLED: u8 = 0b001_0000;
+const VBLANK_INTERRUPT_ENABLED: u8 = 0b0001_0000;
 const HBLANK_INTERRUPT_ENABLED: u8 = 0b0000_1000;
 const FRAMEBUFFER_WIDTH: usize = 160;
 const FRAMEBUFFER_HEIGTH: usize = 144;
 const FRAMEBUFFER_SIZE: usize = FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGTH * 4; // RGBA, so 4 u8s
+const SPRITES_IN_OAM: usize = 40;
+const MAX_SPRITES_PER_LINE: usize = 10;
+const DOTS_PER_LINE: u16 = 456;
+const OAM_SEARCH_DOTS: u16 = 80;
+const LINES_PER_FRAME: u8 = 154;
+// The first tile fetched on every line is thrown away, delaying the first pixel
+const FIRST_FETCH_DOTS: u8 = 6;
+const SPRITE_FETCH_DOTS: u8 = 6;
 
 #[derive(Debug, PartialEq, Eq)]
 struct Color {
@@ -48,11 +57,46 @@ pub struct GPU {
     pub vram: [u8; VRAM_SIZE],
     pub oam: [u8; OAM_SIZE],
     pub framebuffer: [u8; FRAMEBUFFER_SIZE],
-    mode_counter: u32,
+    // Dots elapsed since the start of the current line
+    line_dots: u16,
     line: u8,
+    window_line: u8,
     mode: GPU_modes,
     pub vblank_interrupt_req: bool,
     pub stat_interrupt_req: bool,
+    // OR of all the enabled STAT sources, the interrupt only fires on its rising edge
+    stat_line: bool,
+    ly_coincidence: bool,
+    // The first line after turning the LCD on has no OAM scan
+    first_line_after_enable: bool,
+    // The first frame after turning the LCD on isn't sent to the screen
+    skip_frame: bool,
+
+    // Pixel FIFO state
+    line_sprites: Vec<Sprite>,
+    bg_fifo: VecDeque<u8>,
+    obj_fifo: VecDeque<ObjPixel>,
+    fetcher_step: FetcherStep,
+    // Every fetcher step but Push takes two dots, this is set on the first one
+    fetcher_first_dot: bool,
+    fetcher_x: u8,
+    fetcher_tile: u8,
+    fetcher_data_low: u8,
+    fetcher_data_high: u8,
+    fetching_window: bool,
+    // WY matched LY at some point during this frame
+    window_y_triggered: bool,
+    // The window was drawn on this line, so the window line counter has to move
+    window_drawn: bool,
+    // Sprite waiting for the background fetcher, and the dots its own fetch takes
+    pending_sprite: Option<Sprite>,
+    sprite_fetch_dots: u8,
+    // Dots the pixel output is stalled at the start of the line
+    stall_dots: u8,
+    // Pixels thrown away before reaching the LCD (SCX fine scroll and WX < 7)
+    pixels_to_discard: u8,
+    // Pixels already sent to the LCD on this line
+    lx: u8,
 
     scy: u8,
     scx: u8,
@@ -67,6 +111,58 @@ pub struct GPU {
     wx: u8,
 }
 
+// One OAM entry, with the position already translated to screen coordinates
+#[derive(Clone, Copy)]
+struct Sprite {
+    y: i16,
+    x: i16,
+    tile: u8,
+    flags: u8,
+}
+
+impl Sprite {
+    fn from_oam(oam: &[u8; OAM_SIZE], index: usize) -> Sprite {
+        Sprite {
+            y: oam[index * 4] as i16 - 16,
+            x: oam[index * 4 + 1] as i16 - 8,
+            tile: oam[index * 4 + 2],
+            flags: oam[index * 4 + 3],
+        }
+    }
+
+    fn behind_background(&self) -> bool {
+        return self.flags.get_bit(7);
+    }
+
+    fn y_flip(&self) -> bool {
+        return self.flags.get_bit(6);
+    }
+
+    fn x_flip(&self) -> bool {
+        return self.flags.get_bit(5);
+    }
+
+    fn uses_obp1(&self) -> bool {
+        return self.flags.get_bit(4);
+    }
+}
+
+// A sprite pixel waiting in the OBJ FIFO
+#[derive(Clone, Copy)]
+struct ObjPixel {
+    color_id: u8,
+    uses_obp1: bool,
+    behind_background: bool,
+}
+
+#[derive(PartialEq, Copy, Clone)]
+enum FetcherStep {
+    GetTile,
+    GetDataLow,
+    GetDataHigh,
+    Push,
+}
+
 #[derive(PartialEq, Copy, Clone)]
 enum GPU_modes {
     OAMSearch,
@@ -81,19 +177,43 @@ impl GPU {
             vram: [0; VRAM_SIZE],
             oam: [0; OAM_SIZE],
             framebuffer: [0; FRAMEBUFFER_SIZE],
-            mode_counter: 0,
+            line_dots: 0,
             line: 0,
+            window_line: 0,
             mode: GPU_modes::OAMSearch,
             vblank_interrupt_req: false,
             stat_interrupt_req: false,
-
-            lcdc: 0,
+            stat_line: false,
+            ly_coincidence: false,
+            first_line_after_enable: false,
+            skip_frame: false,
+
+            line_sprites: Vec::with_capacity(MAX_SPRITES_PER_LINE),
+            bg_fifo: VecDeque::with_capacity(16),
+            obj_fifo: VecDeque::with_capacity(8),
+            fetcher_step: FetcherStep::GetTile,
+            fetcher_first_dot: true,
+            fetcher_x: 0,
+            fetcher_tile: 0,
+            fetcher_data_low: 0,
+            fetcher_data_high: 0,
+            fetching_window: false,
+            window_y_triggered: false,
+            window_drawn: false,
+            pending_sprite: None,
+            sprite_fetch_dots: 0,
+            stall_dots: 0,
+            pixels_to_discard: 0,
+            lx: 0,
+
+            // Values left by the boot ROM, which turns the LCD on
+            lcdc: 0x91,
             stat: 0,
             scy: 0,
             scx: 0,
             //ly: 0,
             lyc: 0,
-            bgp: 0,
+            bgp: 0xFC,
             obp0: 0,
             obp1: 0,
             wx: 0,
@@ -117,58 +237,311 @@ impl GPU {
         return self.oam[address];
     }
 
-    fn draw_scanline(&mut self) {
-        if self.lcd_on() && (self.line as usize) < FRAMEBUFFER_HEIGTH {
-            // Background
-            let line_offset = self.line as usize * FRAMEBUFFER_WIDTH * 4;
-            let tilemap_vram_offset: usize = if self.lcdc.get_bit(3) { 0x1C00 } else { 0x1800 };
-            let mut unsigned: bool = true;
-            let tiledata_vram_offset: usize;
-            if self.lcdc.get_bit(4) {
-                tiledata_vram_offset = 0;
-            } else {
-                unsigned = false;
-                tiledata_vram_offset = 0x0800;
+    pub fn update(&mut self, cycles: u8) {
+        if !self.lcd_on() {
+            return;
+        }
+
+        for _ in 0..cycles {
+            self.step_dot();
+        }
+    }
+
+    fn step_dot(&mut self) {
+        match self.mode {
+            GPU_modes::OAMSearch => {
+                // Each OAM entry takes 2 dots to check
+                if self.line_dots % 2 == 0 {
+                    self.oam_scan_entry((self.line_dots / 2) as usize);
+                }
             }
-            let line_y = self.line.wrapping_add(self.scy); // This acts as a 256 modulo
-            let tile_row: u16 = ((line_y as u16) >> 3) << 5;
-            // Background pass
-            for pixel in 0..FRAMEBUFFER_WIDTH {
-                let line_x = (pixel as u8).wrapping_add(self.scx); // This acts as a 256 modulo
-                let tile_column = line_x >> 3;
-                let tile_address: usize =
-                    tilemap_vram_offset + tile_row as usize + tile_column as usize;
-                let tile_num = if unsigned {
-                    self.vram[tile_address] as u16 as i16
-                } else {
-                    self.vram[tile_address] as i8 as i16
-                };
+            GPU_modes::ActivePicture => self.step_active_picture(),
+            GPU_modes::HBlank | GPU_modes::VBlank => {}
+        }
 
-                let tile_location: usize = if unsigned {
-                    tiledata_vram_offset + ((tile_num as u16) << 4) as usize
-                } else {
-                    tiledata_vram_offset + ((tile_num + 128) << 4) as usize
-                };
+        self.line_dots += 1;
+        if self.line_dots == OAM_SEARCH_DOTS
+            && (self.mode == GPU_modes::OAMSearch || self.first_line_after_enable)
+        {
+            self.first_line_after_enable = false;
+            self.start_active_picture();
+        }
+        if self.line_dots == DOTS_PER_LINE {
+            self.line_dots = 0;
+            self.next_line();
+        }
+    }
 
-                let line = (line_y as u16 % 8) * 2;
-                let data1: u8 = self.vram[tile_location + line as usize];
-                let data2: u8 = self.vram[tile_location + (line + 1) as usize];
+    fn next_line(&mut self) {
+        if self.window_drawn {
+            self.window_line = self.window_line.wrapping_add(1);
+            self.window_drawn = false;
+        }
+        self.line += 1;
+        // 144 lines + 10 of VBLANK
+        if self.line == LINES_PER_FRAME {
+            self.line = 0;
+            self.window_line = 0;
+            self.window_y_triggered = false;
+        }
+
+        self.update_ly_coincidence();
 
-                let color_bit = ((line_x as i32 % 8) - 7) * -1;
-                let color_num = ((data2 >> color_bit) & 0b1) << 1;
-                let color_num = color_num | ((data1 >> color_bit) & 0b1);
-                let color = self.get_color(color_num, self.bgp);
+        if self.line as usize == FRAMEBUFFER_HEIGTH {
+            self.skip_frame = false;
+            self.change_mode(GPU_modes::VBlank);
+        } else if (self.line as usize) < FRAMEBUFFER_HEIGTH {
+            self.start_oam_search();
+        }
+    }
 
-                let index = line_offset + pixel * 4;
-                self.framebuffer[index] = color.r;
-                self.framebuffer[index + 1] = color.g;
-                self.framebuffer[index + 2] = color.b;
-                self.framebuffer[index + 3] = color.a;
+    fn start_oam_search(&mut self) {
+        self.line_sprites.clear();
+        if self.line == self.wy {
+            self.window_y_triggered = true;
+        }
+        self.change_mode(GPU_modes::OAMSearch);
+    }
+
+    // OAM scan only picks the first 10 sprites on the line, even if they are off screen horizontally
+    fn oam_scan_entry(&mut self, index: usize) {
+        if index >= SPRITES_IN_OAM || self.line_sprites.len() == MAX_SPRITES_PER_LINE {
+            return;
+        }
+        let sprite_height: i16 = if self.lcdc.get_bit(2) { 16 } else { 8 };
+        let line = self.line as i16;
+        let sprite = Sprite::from_oam(&self.oam, index);
+        if line >= sprite.y && line < sprite.y + sprite_height {
+            self.line_sprites.push(sprite);
+        }
+    }
+
+    fn start_active_picture(&mut self) {
+        self.bg_fifo.clear();
+        self.obj_fifo.clear();
+        self.reset_fetcher(false);
+        self.window_drawn = false;
+        self.pending_sprite = None;
+        self.lx = 0;
+        self.stall_dots = FIRST_FETCH_DOTS;
+        // SCX fine scroll is done by dropping the first pixels, which makes mode 3 longer
+        self.pixels_to_discard = self.scx & 0x07;
+        self.change_mode(GPU_modes::ActivePicture);
+    }
+
+    fn reset_fetcher(&mut self, window: bool) {
+        self.fetching_window = window;
+        self.fetcher_step = FetcherStep::GetTile;
+        self.fetcher_first_dot = true;
+        self.fetcher_x = 0;
+    }
+
+    fn step_active_picture(&mut self) {
+        if self.stall_dots > 0 {
+            self.stall_dots -= 1;
+            return;
+        }
+
+        // The window restarts the background fetcher as soon as the LCD reaches WX
+        if !self.fetching_window && self.window_starts_here() {
+            self.bg_fifo.clear();
+            self.reset_fetcher(true);
+            self.window_drawn = true;
+            if self.wx < 7 {
+                self.pixels_to_discard = 7 - self.wx;
             }
+        }
 
-            if self.sprites_on() {}
+        // A sprite at the current X stops the pixel output until it is fetched
+        if self.pending_sprite.is_none() && self.sprites_on() {
+            let lx = self.lx as i16;
+            if let Some(index) = self.line_sprites.iter().position(|sprite| sprite.x <= lx) {
+                self.pending_sprite = Some(self.line_sprites.remove(index));
+                self.sprite_fetch_dots = SPRITE_FETCH_DOTS;
+            }
+        }
+
+        if let Some(sprite) = self.pending_sprite {
+            // The background fetcher gets to finish its current tile first
+            if self.fetcher_step != FetcherStep::Push {
+                self.step_fetcher();
+                return;
+            }
+            self.sprite_fetch_dots -= 1;
+            if self.sprite_fetch_dots == 0 {
+                self.load_sprite(&sprite);
+                self.pending_sprite = None;
+            }
+            return;
         }
+
+        self.step_fetcher();
+        self.shift_pixel();
+    }
+
+    fn window_starts_here(&self) -> bool {
+        return self.window_on()
+            && self.background_on()
+            && self.window_y_triggered
+            && self.wx <= 166
+            && self.lx as i16 >= self.wx as i16 - 7;
     }
+
+    fn step_fetcher(&mut self) {
+        if self.fetcher_step == FetcherStep::Push {
+            // The fetched tile waits until the FIFO is empty
+            if self.bg_fifo.is_empty() {
+                for bit in (0..8).rev() {
+                    let color_num = ((self.fetcher_data_high >> bit) & 0b1) << 1;
+                    let color_num = color_num | ((self.fetcher_data_low >> bit) & 0b1);
+                    // On DMG, LCDC bit 0 blanks both the background and the window
+                    self.bg_fifo
+                        .push_back(if self.background_on() { color_num } else { 0 });
+                }
+                self.fetcher_x = self.fetcher_x.wrapping_add(1);
+                self.fetcher_step = FetcherStep::GetTile;
+                self.fetcher_first_dot = true;
+            }
+            return;
+        }
+
+        if self.fetcher_first_dot {
+            self.fetcher_first_dot = false;
+            return;
+        }
+        self.fetcher_first_dot = true;
+
+        // Registers are read while fetching, so mid-line writes show up on screen
+        match self.fetcher_step {
+            FetcherStep::GetTile => {
+                self.fetcher_tile = self.vram[self.fetcher_tile_address()];
+                self.fetcher_step = FetcherStep::GetDataLow;
+            }
+            FetcherStep::GetDataLow => {
+                self.fetcher_data_low = self.vram[self.fetcher_data_address()];
+                self.fetcher_step = FetcherStep::GetDataHigh;
+            }
+            FetcherStep::GetDataHigh => {
+                self.fetcher_data_high = self.vram[self.fetcher_data_address() + 1];
+                self.fetcher_step = FetcherStep::Push;
+            }
+            FetcherStep::Push => {}
+        }
+    }
+
+    fn fetcher_tile_address(&self) -> usize {
+        if self.fetching_window {
+            let tilemap_vram_offset: usize = if self.lcdc.get_bit(6) { 0x1C00 } else { 0x1800 };
+            let tile_row = ((self.window_line as usize) >> 3) << 5;
+            return tilemap_vram_offset + tile_row + (self.fetcher_x as usize & 0x1F);
+        }
+        let tilemap_vram_offset: usize = if self.lcdc.get_bit(3) { 0x1C00 } else { 0x1800 };
+        let line_y = self.line.wrapping_add(self.scy); // This acts as a 256 modulo
+        let tile_row = ((line_y as usize) >> 3) << 5;
+        let tile_column = ((self.scx >> 3) as usize + self.fetcher_x as usize) & 0x1F;
+        return tilemap_vram_offset + tile_row + tile_column;
+    }
+
+    // Address of the low byte of the current row of the fetched tile
+    fn fetcher_data_address(&self) -> usize {
+        let y = if self.fetching_window {
+            self.window_line
+        } else {
+            self.line.wrapping_add(self.scy)
+        };
+        let tile_location: usize = if self.lcdc.get_bit(4) {
+            // 0x8000 addressing, unsigned tile numbers
+            (self.fetcher_tile as usize) << 4
+        } else {
+            // 0x8800 addressing, signed tile numbers relative to 0x9000
+            let tile_num = self.fetcher_tile as i8 as i16;
+            0x0800 + ((tile_num + 128) << 4) as usize
+        };
+        return tile_location + (y as usize % 8) * 2;
+    }
+
+    // Mixes the sprite pixels into the OBJ FIFO, pixels from earlier sprites win over later ones
+    fn load_sprite(&mut self, sprite: &Sprite) {
+        let sprite_height: i16 = if self.lcdc.get_bit(2) { 16 } else { 8 };
+        // The sprite was scanned with the height at OAM search, which may have changed since
+        let mut sprite_line = (self.line as i16 - sprite.y) as usize & (sprite_height as usize - 1);
+        if sprite.y_flip() {
+            sprite_line = (sprite_height - 1) as usize - sprite_line;
+        }
+        // In 8x16 mode the lowest bit of the tile number is ignored
+        let tile = if sprite_height == 16 {
+            sprite.tile & 0xFE
+        } else {
+            sprite.tile
+        };
+        // Sprites always use the 0x8000 addressing mode
+        let tile_location = ((tile as usize) << 4) + sprite_line * 2;
+        let data1: u8 = self.vram[tile_location];
+        let data2: u8 = self.vram[tile_location + 1];
+
+        // Sprites partially off the left of the screen lose their first pixels
+        let skipped = (self.lx as i16 - sprite.x) as usize;
+        for pixel in skipped..8 {
+            let color_bit = if sprite.x_flip() { pixel } else { 7 - pixel };
+            let color_num = ((data2 >> color_bit) & 0b1) << 1;
+            let color_num = color_num | ((data1 >> color_bit) & 0b1);
+            let obj_pixel = ObjPixel {
+                color_id: color_num,
+                uses_obp1: sprite.uses_obp1(),
+                behind_background: sprite.behind_background(),
+            };
+            let slot = pixel - skipped;
+            if slot < self.obj_fifo.len() {
+                // Color 0 is transparent, so a lower priority sprite can still show up
+                if self.obj_fifo[slot].color_id == 0 {
+                    self.obj_fifo[slot] = obj_pixel;
+                }
+            } else {
+                self.obj_fifo.push_back(obj_pixel);
+            }
+        }
+    }
+
+    fn shift_pixel(&mut self) {
+        let bg_color_id = match self.bg_fifo.pop_front() {
+            Some(color_id) => color_id,
+            None => return,
+        };
+        if self.pixels_to_discard > 0 {
+            self.pixels_to_discard -= 1;
+            return;
+        }
+
+        let color = match self.obj_fifo.pop_front() {
+            Some(obj_pixel)
+                if obj_pixel.color_id != 0
+                    && self.sprites_on()
+                    && !(obj_pixel.behind_background && bg_color_id != 0) =>
+            {
+                let palette = if obj_pixel.uses_obp1 {
+                    self.obp1
+                } else {
+                    self.obp0
+                };
+                self.get_color(obj_pixel.color_id, palette)
+            }
+            _ => self.get_color(bg_color_id, self.bgp),
+        };
+
+        if !self.skip_frame {
+            let index = (self.line as usize * FRAMEBUFFER_WIDTH + self.lx as usize) * 4;
+            self.framebuffer[index] = color.r;
+            self.framebuffer[index + 1] = color.g;
+            self.framebuffer[index + 2] = color.b;
+            self.framebuffer[index + 3] = color.a;
+        }
+
+        self.lx += 1;
+        if self.lx as usize == FRAMEBUFFER_WIDTH {
+            self.change_mode(GPU_modes::HBlank);
+        }
+    }
+
     fn get_color(&self, color_id: u8, palette_num: u8) -> Color {
         let (hi, lo) = match color_id {
             0 => (1, 0),
@@ -190,111 +563,62 @@ impl GPU {
         }
     }
 
-    pub fn update_scanlines(&mut self, cycles: u8) {
-        if !self.lcd_on() {
-            return;
-        }
-
-        let mut dots_left = cycles;
-
-        while dots_left > 0 {
-            let iteration_dots;
-
-            if dots_left >= 80 {
-                iteration_dots = 80;
-            } else {
-                iteration_dots = dots_left;
-            }
-
-            self.mode_counter += iteration_dots as u32;
-            dots_left -= iteration_dots;
-
-            // We finished a line (114 cpu cycles or 456 dots)
-            if self.mode_counter >= 456 {
-                self.mode_counter -= 456;
-                // 144 lines + 10 of VBLANK
-                if (self.line as usize) < FRAMEBUFFER_HEIGTH {
-                    self.draw_scanline();
-                }
-                self.line = self.line + 1;
-                if self.line == 154 {
-                    self.line = 0;
-                }
-
-                self.check_lyc_interrupt();
-            }
-
-            if self.line >= 144 && self.mode != GPU_modes::VBlank {
-                self.change_mode(GPU_modes::VBlank)
-            } else if self.line < 144 {
-                if self.mode_counter <= 80 && self.mode != GPU_modes::OAMSearch {
-                    self.change_mode(GPU_modes::OAMSearch);
-                }
-                // TODO maybe actually use the correct value based on window and scrolling register
-                else if self.mode_counter <= 252 && self.mode != GPU_modes::ActivePicture {
-                    self.change_mode(GPU_modes::ActivePicture);
-                } else {
-                    self.change_mode(GPU_modes::HBlank);
-                }
-            }
-        }
+    // OAM is in use by the PPU during OAM scan and pixel transfer
+    pub fn oam_blocked(&self) -> bool {
+        return match self.mode {
+            GPU_modes::OAMSearch | GPU_modes::ActivePicture => true,
+            _ => false,
+        };
     }
 
     fn lcd_on(&self) -> bool {
         return self.lcdc & 0x80 == 0x80;
     }
 
-    fn sprites_on(&self) -> bool {
-        return self.lcdc.get_bit(1);
+    fn window_on(&self) -> bool {
+        return self.lcdc.get_bit(5);
     }
 
-    fn mode_interrupt_enabled(&self, mode: GPU_modes) -> bool {
-        match mode {
-            GPU_modes::HBlank => {
-                return self.lcdc & HBLANK_INTERRUPT_ENABLED == HBLANK_INTERRUPT_ENABLED
-            }
-            GPU_modes::VBlank => {
-                return self.lcdc & VBLANK_INTERRUPT_ENABLED == VBLANK_INTERRUPT_ENABLED
-            }
-            GPU_modes::OAMSearch => {
-                return self.lcdc & OAM_INTERRUPT_ENABLED == OAM_INTERRUPT_ENABLED
-            }
-            _ => return false,
-        }
+    fn background_on(&self) -> bool {
+        return self.lcdc.get_bit(0);
+    }
+
+    fn sprites_on(&self) -> bool {
+        return self.lcdc.get_bit(1);
     }
 
-    fn lyc_interrupt_enabled(&self) -> bool {
-        return self.stat & LYC_INTERRUPT_ENABLED == LYC_INTERRUPT_ENABLED;
+    fn stat_sources_active(&self) -> bool {
+        let mode_source = match self.mode {
+            GPU_modes::HBlank => self.stat & HBLANK_INTERRUPT_ENABLED == HBLANK_INTERRUPT_ENABLED,
+            GPU_modes::VBlank => self.stat & VBLANK_INTERRUPT_ENABLED == VBLANK_INTERRUPT_ENABLED,
+            GPU_modes::OAMSearch => self.stat & OAM_INTERRUPT_ENABLED == OAM_INTERRUPT_ENABLED,
+            GPU_modes::ActivePicture => false,
+        };
+        let lyc_source =
+            self.ly_coincidence && self.stat & LYC_INTERRUPT_ENABLED == LYC_INTERRUPT_ENABLED;
+        return mode_source || lyc_source;
     }
 
-    fn check_lyc_interrupt(&mut self) {
-        if self.lyc == self.line && self.lyc_interrupt_enabled() {
+    // STAT blocking: while any source keeps the line high, new sources don't fire another interrupt
+    fn update_stat_interrupt(&mut self) {
+        let stat_line = self.stat_sources_active();
+        if stat_line && !self.stat_line {
             self.stat_interrupt_req = true;
         }
+        self.stat_line = stat_line;
     }
-    fn change_mode(&mut self, new_mode: GPU_modes) {
-        self.stat &= 0b0111_1100;
-        self.mode = new_mode;
-        match self.mode {
-            // TODO
-            GPU_modes::VBlank => {
-                self.stat |= 0b0000_0001;
-                self.vblank_interrupt_req = true;
-            }
 
-            GPU_modes::OAMSearch => {
-                self.stat |= 0b0000_0010;
-            }
-
-            GPU_modes::ActivePicture => {
-                self.stat |= 0b0000_0011;
-            }
+    fn update_ly_coincidence(&mut self) {
+        self.ly_coincidence = self.line == self.lyc;
+        self.update_stat_interrupt();
+    }
 
-            GPU_modes::HBlank => {}
-        }
-        if self.mode_interrupt_enabled(self.mode) {
-            self.stat_interrupt_req = true;
+    fn change_mode(&mut self, new_mode: GPU_modes) {
+        self.mode = new_mode;
+        if self.mode == GPU_modes::VBlank {
+            self.vblank_interrupt_req = true;
         }
+        self.update_stat_interrupt();
     }
 
     pub fn lcdc(&self) -> u8 {
@@ -302,27 +626,77 @@ impl GPU {
     }
 
     pub fn set_lcdc(&mut self, value: u8) {
+        let was_on = self.lcd_on();
         self.lcdc = value;
+        if was_on && !self.lcd_on() {
+            self.turn_lcd_off();
+        } else if !was_on && self.lcd_on() {
+            self.turn_lcd_on();
+        }
+    }
+
+    fn turn_lcd_off(&mut self) {
+        // LY and the STAT mode read 0 while the LCD is off
+        self.line = 0;
+        self.line_dots = 0;
+        self.window_line = 0;
+        self.window_y_triggered = false;
+        self.window_drawn = false;
+        self.mode = GPU_modes::HBlank;
+        self.stat_line = false;
+        for pixel in self.framebuffer.chunks_mut(4) {
+            pixel[0] = WHITE.r;
+            pixel[1] = WHITE.g;
+            pixel[2] = WHITE.b;
+            pixel[3] = WHITE.a;
+        }
+    }
+
+    fn turn_lcd_on(&mut self) {
+        self.first_line_after_enable = true;
+        self.skip_frame = true;
+        self.line_sprites.clear();
+        if self.line == self.wy {
+            self.window_y_triggered = true;
+        }
+        self.update_ly_coincidence();
     }
 
     pub fn set_stat(&mut self, value: u8) {
-        // Only modifying Read/Write values
-        self.stat = value & 0b0111_1000
+        // The DMG behaves as if $FF were written for one cycle, and then the written value,
+        // so writing STAT during OAM scan, H-Blank, V-Blank or LY=LYC can trigger the interrupt
+        self.stat = 0b0111_1000;
+        self.update_stat_interrupt();
 
-        // TODO Game Boy makes the LCD interrupt sometimes trigger when writing to STAT
-        // (including writing $00) during OAM scan, H-Blank, V-Blank, or LY=LYC.
-        // It behaves as if $FF were written for one cycle, and then the written
-        // value were written the next cycle.
+        // Only modifying Read/Write values
+        self.stat = value & 0b0111_1000;
+        self.update_stat_interrupt();
     }
 
     pub fn stat(&self) -> u8 {
-        return self.stat;
+        let mode: u8 = match self.mode {
+            GPU_modes::HBlank => 0b00,
+            GPU_modes::VBlank => 0b01,
+            GPU_modes::OAMSearch => 0b10,
+            GPU_modes::ActivePicture => 0b11,
+        };
+        let coincidence: u8 = if self.ly_coincidence { 0b0000_0100 } else { 0 };
+        // Bit 7 is unused and always reads 1
+        return 0b1000_0000 | self.stat | coincidence | mode;
+    }
+
+    pub fn scy(&self) -> u8 {
+        return self.scy;
     }
 
     pub fn set_scy(&mut self, value: u8) {
         self.scy = value;
     }
 
+    pub fn scx(&self) -> u8 {
+        return self.scx;
+    }
+
     pub fn set_scx(&mut self, value: u8) {
         self.scx = value;
     }
@@ -331,8 +705,13 @@ impl GPU {
         return self.line;
     }
 
+    pub fn lyc(&self) -> u8 {
+        return self.lyc;
+    }
+
     pub fn set_lyc(&mut self, value: u8) {
         self.lyc = value;
+        self.update_ly_coincidence();
     }
 
     pub fn bgp(&self) -> u8 {
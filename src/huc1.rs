@@ -0,0 +1,111 @@
+use crate::mbc;
+use crate::mbc::*;
+use crate::memory_map::*;
+use std::path::PathBuf;
+
+// Writing this to 0x0000-0x1FFF maps the infrared port instead of the RAM
+const IR_MODE: u8 = 0x0E;
+// What the IR receiver reads when it sees no light, there's never an IR peer attached
+const IR_NO_LIGHT: u8 = 0xC0;
+
+pub struct HuC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+    save_path: PathBuf,
+    ram_dirty: bool,
+    ir_mode: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+}
+
+impl mbc::MBC for HuC1 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address as usize {
+            BANK0_START..=BANK0_END => return self.rom[address as usize],
+            BANKN_START..=BANKN_END => {
+                let bank = self.rom_bank as usize % (self.rom.len() / ROM_BANK_SIZE);
+                return self.rom[bank * ROM_BANK_SIZE + (address as usize - BANKN_START)];
+            }
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => {
+                if self.ir_mode {
+                    return IR_NO_LIGHT;
+                }
+                if self.ram.is_empty() {
+                    return 0xFF;
+                }
+                return self.ram[self.ram_offset(address)];
+            }
+            _ => return 0xFF,
+        }
+    }
+    fn read_word(&self, address: u16) -> u16 {
+        return (self.read_byte(address) as u16) | ((self.read_byte(address + 1) as u16) << 8);
+    }
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address as usize {
+            // IR Select, any other value maps the RAM back
+            0x0000..=0x1FFF => self.ir_mode = value == IR_MODE,
+            // ROM Bank Number
+            0x2000..=0x3FFF => {
+                self.rom_bank = value & 0x3F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            // RAM Bank Number
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => {
+                // Writes in IR mode toggle the IR LED, which nobody is looking at
+                if !self.ir_mode && !self.ram.is_empty() {
+                    let offset = self.ram_offset(address);
+                    self.ram[offset] = value;
+                    self.ram_dirty = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    fn write_word(&mut self, address: u16, value: u16) {
+        self.write_byte(address, value as u8);
+        self.write_byte(address + 1, (value >> 8) as u8);
+    }
+
+    fn open_rom(&mut self, rom_path: PathBuf) {
+        self.rom = mbc::load_rom(&rom_path);
+        self.ram = vec![0; mbc::ram_size(&self.rom)];
+        self.battery = mbc::has_battery(&self.rom);
+        self.save_path = mbc::save_path(&rom_path);
+        if self.battery {
+            mbc::load_save_file(&self.save_path, &mut self.ram);
+        }
+        println!("The game uses HuC1, so it's supported. Continuing...");
+    }
+
+    fn save_ram(&mut self) {
+        if self.battery && self.ram_dirty {
+            mbc::write_save_file(&self.save_path, &self.ram);
+            self.ram_dirty = false;
+        }
+    }
+}
+
+impl HuC1 {
+    pub fn new() -> HuC1 {
+        HuC1 {
+            rom: Vec::new(),
+            ram: Vec::new(),
+            battery: false,
+            save_path: PathBuf::new(),
+            ram_dirty: false,
+            ir_mode: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> usize {
+        let bank = self.ram_bank as usize;
+        return (bank * RAM_BANK_SIZE + (address as usize - EXTERNAL_RAM_START)) % self.ram.len();
+    }
+}
@@ -1,5 +1,6 @@
 pub struct InterruptController {
     interrupt_master_enable: bool,
+    ime_scheduled: bool,
     ie: u8,
     pub iflag: u8,
 }
@@ -18,16 +19,18 @@ impl InterruptController {
     pub fn new() -> InterruptController {
         InterruptController {
             interrupt_master_enable: false,
+            ime_scheduled: false,
             ie: 0,
             iflag: 0,
         }
     }
 
     pub fn set_iflag(&mut self, value: u8) {
-        self.iflag = value;
+        self.iflag = value & 0b0001_1111;
     }
     pub fn iflag(&self) -> u8 {
-        return self.iflag;
+        // The upper 3 bits are unused and always read 1
+        return 0b1110_0000 | self.iflag;
     }
     pub fn set_ie(&mut self, value: u8) {
         self.ie = value;
@@ -37,13 +40,24 @@ impl InterruptController {
     }
     pub fn disable_master_interrupt(&mut self) {
         self.interrupt_master_enable = false;
+        self.ime_scheduled = false;
     }
     pub fn enable_master_interrupt(&mut self) {
         self.interrupt_master_enable = true;
+        self.ime_scheduled = false;
+    }
+    pub fn schedule_master_interrupt(&mut self) {
+        self.ime_scheduled = true;
+    }
+    pub fn ime_scheduled(&self) -> bool {
+        return self.ime_scheduled;
     }
     pub fn ime(&self) -> bool {
         return self.interrupt_master_enable;
     }
+    pub fn interrupt_pending(&self) -> bool {
+        return self.iflag & self.ie & 0x1F != 0;
+    }
     pub fn set_interrupt_flag(&mut self, flag: InterruptFlags) {
         //println!("{:?}", flag);
         //println!("{}", self.iflag);
@@ -62,16 +62,26 @@ impl Joypad {
         println!("Key released! {:?}", key);
     }
 
+    pub fn any_key_pressed(&self) -> bool {
+        return self.buttons_pressed & 0x0F != 0x0F || self.directions_pressed & 0x0F != 0x0F;
+    }
+
     pub fn joyp(&self) -> u8 {
+        let mut keys = 0x0F;
         // Button Keys
         if self.joyp & 0b0010_0000 == 0 {
-            return self.buttons_pressed;
-        } else {
-            return self.directions_pressed;
+            keys &= self.buttons_pressed;
+        }
+        // Direction Keys
+        if self.joyp & 0b0001_0000 == 0 {
+            keys &= self.directions_pressed;
         }
+        // Bits 6 and 7 are unused and always read 1
+        return 0b1100_0000 | self.joyp | keys;
     }
 
     pub fn set_joyp(&mut self, value: u8) {
-        self.joyp |= value & 0b0011_0000;
+        // Only the select bits are writable
+        self.joyp = value & 0b0011_0000;
     }
 }
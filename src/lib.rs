@@ -5,14 +5,25 @@ pub mod cli;
 pub mod device;
 
 mod apu;
+mod audio_recorder;
+mod audio_stream;
 mod cpu;
+mod gbs;
 mod gpu;
+mod huc1;
 mod interrupt_controller;
 mod joypad;
 mod link_cable;
 mod mbc;
 mod mbc0;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
 mod memory_map;
 mod mmu;
+mod printer;
 mod registers;
+mod rtc;
+mod tcp_link;
 mod timer;
@@ -1,11 +1,106 @@
+use std::io;
+use std::io::Write;
+
+// The internal clock shifts one bit at 8192 Hz
+const DOTS_PER_BIT: u16 = 512;
+
+// Whatever is plugged into the other end of the cable
+pub trait LinkPeer {
+    // This side clocks a byte out with its internal clock, returns the byte shifted back in
+    fn exchange(&mut self, outgoing: u8) -> io::Result<u8>;
+    // Lets the peer run alongside the emulation, `outgoing` is what it gets if it clocks a transfer
+    // itself, in which case the byte it sent is returned
+    fn poll(&mut self, cycles: u8, outgoing: u8) -> io::Result<Option<u8>>;
+}
+
 pub struct LinkCable {
     sb: u8,
     sc: u8,
+    bits_left: u8,
+    bit_dots: u16,
+    peer: Option<Box<dyn LinkPeer>>,
+    // Byte being shifted in by the internal clock
+    incoming: u8,
+    // Transmitted bytes are printed, test ROMs report their results this way
+    echo_to_stdout: bool,
+    pub serial_interrupt_req: bool,
 }
 
 impl LinkCable {
     pub fn new() -> LinkCable {
-        LinkCable { sb: 0, sc: 0 }
+        LinkCable {
+            sb: 0,
+            sc: 0,
+            bits_left: 0,
+            bit_dots: 0,
+            peer: None,
+            incoming: 0xFF,
+            echo_to_stdout: false,
+            serial_interrupt_req: false,
+        }
+    }
+
+    pub fn set_echo_to_stdout(&mut self, echo: bool) {
+        self.echo_to_stdout = echo;
+    }
+
+    pub fn attach(&mut self, peer: Box<dyn LinkPeer>) {
+        self.peer = Some(peer);
+    }
+
+    // Unplugs whatever is attached, letting it finish its work
+    pub fn detach(&mut self) {
+        self.peer = None;
+    }
+
+    pub fn update(&mut self, cycles: u8) {
+        let result = match &mut self.peer {
+            Some(peer) => peer.poll(cycles, self.sb),
+            None => Ok(None),
+        };
+        match result {
+            // The peer's clock shifts SB whatever SC says, only a transfer we started gets completed
+            Ok(Some(byte)) => {
+                self.sb = byte;
+                if self.transferring() {
+                    self.finish_transfer();
+                }
+            }
+            Ok(None) => {}
+            Err(error) => self.disconnect(error),
+        }
+
+        if !self.transferring() || !self.internal_clock() {
+            return;
+        }
+        self.bit_dots += cycles as u16;
+        while self.bit_dots >= DOTS_PER_BIT && self.bits_left > 0 {
+            self.bit_dots -= DOTS_PER_BIT;
+            self.sb = (self.sb << 1) | (self.incoming >> 7);
+            self.incoming <<= 1;
+            self.bits_left -= 1;
+            if self.bits_left == 0 {
+                self.finish_transfer();
+            }
+        }
+    }
+
+    fn finish_transfer(&mut self) {
+        self.sc &= !0b1000_0000;
+        self.serial_interrupt_req = true;
+    }
+
+    fn disconnect(&mut self, error: io::Error) {
+        eprintln!("The link cable was disconnected: {}", error);
+        self.peer = None;
+    }
+
+    fn transferring(&self) -> bool {
+        return self.sc & 0b1000_0000 != 0;
+    }
+
+    fn internal_clock(&self) -> bool {
+        return self.sc & 0b0000_0001 != 0;
     }
 
     pub fn sb(&self) -> u8 {
@@ -17,10 +112,34 @@ impl LinkCable {
     }
 
     pub fn sc(&self) -> u8 {
-        return self.sc;
+        // Only the transfer start and clock select bits exist on the DMG
+        return 0b0111_1110 | self.sc;
     }
 
     pub fn set_sc(&mut self, value: u8) {
-        self.sc = value;
+        self.sc = value & 0b1000_0001;
+        if self.transferring() {
+            self.bits_left = 8;
+            self.bit_dots = 0;
+            if self.internal_clock() {
+                // Only the bytes this side clocks out itself are echoed
+                if self.echo_to_stdout {
+                    print!("{}", self.sb as char);
+                    io::stdout().flush().unwrap_or(());
+                }
+                // Without a partner the line is pulled high, so 1s are received
+                let result = match &mut self.peer {
+                    Some(peer) => peer.exchange(self.sb),
+                    None => Ok(0xFF),
+                };
+                self.incoming = match result {
+                    Ok(byte) => byte,
+                    Err(error) => {
+                        self.disconnect(error);
+                        0xFF
+                    }
+                };
+            }
+        }
     }
 }
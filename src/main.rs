@@ -3,6 +3,7 @@
 extern crate config;
 
 use gmbr::device::Device;
+use std::path::PathBuf;
 
 fn main() {
     let mut settings = config::Config::default();
@@ -11,6 +12,37 @@ fn main() {
         .unwrap();
     let mut device: Device = Device::new();
     let rom_path = gmbr::cli::choose_rom(&settings);
+    // GBS music rips are played without a window
+    if rom_path
+        .extension()
+        .map_or(false, |extension| extension == "gbs")
+    {
+        device.play_gbs(rom_path);
+        return;
+    }
     device.open_rom(rom_path);
+    // Test ROMs like Blargg's print their results through the serial port
+    if let Ok(echo) = settings.get_bool("serial_stdout") {
+        device.set_serial_echo(echo);
+    }
+    // Two instances are linked by having one listen and the other connect to it
+    if let Ok(address) = settings.get_str("link_listen") {
+        device.link_over_tcp(&address, true);
+    } else if let Ok(address) = settings.get_str("link_connect") {
+        device.link_over_tcp(&address, false);
+    } else if let Ok(folder) = settings.get_str("printer_folder") {
+        device.attach_printer(PathBuf::from(folder));
+    }
+    // Audio can be recorded from the start, as a stereo mix or one file per channel
+    match settings
+        .get_str("record_audio")
+        .as_ref()
+        .map(String::as_str)
+    {
+        Ok("mix") => device.start_audio_recording(false),
+        Ok("channels") => device.start_audio_recording(true),
+        Ok(other) => eprintln!("Unknown record_audio option {:?}, not recording", other),
+        Err(_) => {}
+    }
     device.run();
 }
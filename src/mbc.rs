@@ -3,7 +3,17 @@ pub const BANK0_START: usize = 0x0000;
 pub const BANK0_END: usize = 0x3FFF;
 pub const BANKN_START: usize = 0x4000;
 pub const BANKN_END: usize = 0x7FFF;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
 use std::path::PathBuf;
+use std::str;
+
+// Cartridge header locations
+pub const HEADER_CARTRIDGE_TYPE: usize = 0x0147;
+pub const HEADER_ROM_SIZE: usize = 0x0148;
+pub const HEADER_RAM_SIZE: usize = 0x0149;
 
 pub trait MBC {
     fn read_byte(&self, address: u16) -> u8;
@@ -11,4 +21,117 @@ pub trait MBC {
     fn write_byte(&mut self, address: u16, value: u8);
     fn write_word(&mut self, address: u16, value: u16);
     fn open_rom(&mut self, rom_path: PathBuf);
+    // Flushes battery backed RAM to the .sav file
+    fn save_ram(&mut self);
+    // Only rumble carts drive a motor
+    fn rumble_active(&self) -> bool {
+        return false;
+    }
+}
+
+pub enum MBCType {
+    MBC0,
+    MBC1,
+    MBC2,
+    MBC3,
+    MBC5,
+    HuC1,
+}
+
+impl MBCType {
+    pub fn from_cartridge_type(cartridge_type: u8) -> MBCType {
+        match cartridge_type {
+            0x00 | 0x08 | 0x09 => MBCType::MBC0,
+            0x01..=0x03 => MBCType::MBC1,
+            0x05 | 0x06 => MBCType::MBC2,
+            0x0F..=0x13 => MBCType::MBC3,
+            0x19..=0x1E => MBCType::MBC5,
+            0xFF => MBCType::HuC1,
+            _ => panic!(
+                "This game uses an unsupported MBC (cartridge type {:#04X}) Closing the emulator...",
+                cartridge_type
+            ),
+        }
+    }
+}
+
+// Reads just the header, so we know which MBC to build before loading the whole ROM
+pub fn read_cartridge_type(rom_path: &PathBuf) -> u8 {
+    let mut rom_file = File::open(rom_path).expect("Error opening the ROM file");
+    let mut header = [0; HEADER_CARTRIDGE_TYPE + 1];
+    rom_file
+        .read_exact(&mut header)
+        .expect("The ROM file is too small to contain a header. Closing the emulator...");
+    return header[HEADER_CARTRIDGE_TYPE];
+}
+
+pub fn load_rom(rom_path: &PathBuf) -> Vec<u8> {
+    let mut rom: Vec<u8> = Vec::new();
+    let mut rom_file = File::open(rom_path).expect("Error opening the ROM file");
+    rom_file.read_to_end(&mut rom).unwrap();
+
+    check_magic_number(&rom);
+
+    let header = &rom[0x0100..0x014F];
+    let mut game_title: String = str::from_utf8(&header[0x34..0x43]).unwrap().to_string();
+    game_title.retain(|c| c != '\0');
+    println!("Opening the game {:#?}", game_title);
+
+    //Rom size is indicated in byte 0x148
+    let rom_size: usize = 32768 << rom[HEADER_ROM_SIZE];
+    assert_eq!(rom.len(), rom_size, "The ROM size reported by the header and the ROM file size don't match. Maybe the file is corrupted. Closing the emulator...");
+    return rom;
+}
+
+// External RAM size in bytes, as indicated in byte 0x149
+pub fn ram_size(rom: &[u8]) -> usize {
+    match rom[HEADER_RAM_SIZE] {
+        0x00 => 0,
+        0x01 => 0x800,
+        0x02 => 0x2000,
+        0x03 => 0x8000,
+        0x04 => 0x20000,
+        0x05 => 0x10000,
+        _ => panic!(
+            "Invalid RAM size in the header: {:#04X}",
+            rom[HEADER_RAM_SIZE]
+        ),
+    }
+}
+
+pub fn has_battery(rom: &[u8]) -> bool {
+    match rom[HEADER_CARTRIDGE_TYPE] {
+        0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0xFF => true,
+        _ => false,
+    }
+}
+
+pub fn save_path(rom_path: &PathBuf) -> PathBuf {
+    return rom_path.with_extension("sav");
+}
+
+// Fills the RAM from the save file, returns whatever was stored after it (like the RTC)
+pub fn load_save_file(save_path: &PathBuf, ram: &mut [u8]) -> Vec<u8> {
+    let data = match fs::read(save_path) {
+        Ok(data) => data,
+        Err(_) => return Vec::new(),
+    };
+    if data.len() < ram.len() {
+        eprintln!("The save file {:?} is too small, ignoring it", save_path);
+        return Vec::new();
+    }
+    ram.copy_from_slice(&data[..ram.len()]);
+    println!("Loaded the save file {:?}", save_path);
+    return data[ram.len()..].to_vec();
+}
+
+pub fn write_save_file(save_path: &PathBuf, data: &[u8]) {
+    if let Err(error) = fs::write(save_path, data) {
+        eprintln!("Couldn't write the save file {:?}: {}", save_path, error);
+    }
+}
+
+fn check_magic_number(rom: &[u8]) {
+    assert_eq!(rom[0x0100], 0x00 as u8, "This is not a GameBoy Game!");
+    assert_eq!(rom[0x0101], 0xC3 as u8, "This is not a GameBoy Game!");
 }
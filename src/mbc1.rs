@@ -0,0 +1,165 @@
+use crate::mbc;
+use crate::mbc::*;
+use crate::memory_map::*;
+use std::path::PathBuf;
+
+pub struct MBC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+    save_path: PathBuf,
+    ram_dirty: bool,
+    ram_enabled: bool,
+    // BANK1, lower 5 bits of the ROM bank number
+    rom_bank: u8,
+    // BANK2, upper ROM bank bits or RAM bank number
+    upper_bank: u8,
+    // Mode 1 applies BANK2 to the 0x0000-0x3FFF area and to the external RAM
+    advanced_banking: bool,
+    // MBC1M multicarts only wire 4 bits of BANK1
+    multicart: bool,
+}
+
+impl mbc::MBC for MBC1 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address as usize {
+            BANK0_START..=BANK0_END => {
+                let bank = if self.advanced_banking {
+                    self.upper_bank_bits()
+                } else {
+                    0
+                };
+                return self.rom[self.rom_offset(bank, address)];
+            }
+            BANKN_START..=BANKN_END => {
+                let bank = self.upper_bank_bits() | self.lower_bank_bits();
+                return self.rom[self.rom_offset(bank, address)];
+            }
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => {
+                if !self.ram_enabled || self.ram.is_empty() {
+                    return 0xFF;
+                }
+                return self.ram[self.ram_offset(address)];
+            }
+            _ => return 0xFF,
+        }
+    }
+    fn read_word(&self, address: u16) -> u16 {
+        return (self.read_byte(address) as u16) | ((self.read_byte(address + 1) as u16) << 8);
+    }
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address as usize {
+            // RAM Enable
+            0x0000..=0x1FFF => {
+                let was_enabled = self.ram_enabled;
+                self.ram_enabled = value & 0x0F == 0x0A;
+                // Games disable the RAM once they are done saving
+                if was_enabled && !self.ram_enabled {
+                    self.save_ram();
+                }
+            }
+            // ROM Bank Number, 0 is treated as 1 because bank 0 can't be mapped twice
+            0x2000..=0x3FFF => {
+                self.rom_bank = value & 0x1F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            // RAM Bank Number or Upper Bits of ROM Bank Number
+            0x4000..=0x5FFF => self.upper_bank = value & 0x03,
+            // Banking Mode Select
+            0x6000..=0x7FFF => self.advanced_banking = value & 0x01 == 0x01,
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => {
+                if self.ram_enabled && !self.ram.is_empty() {
+                    let offset = self.ram_offset(address);
+                    self.ram[offset] = value;
+                    self.ram_dirty = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    fn write_word(&mut self, address: u16, value: u16) {
+        self.write_byte(address, value as u8);
+        self.write_byte(address + 1, (value >> 8) as u8);
+    }
+
+    fn open_rom(&mut self, rom_path: PathBuf) {
+        self.rom = mbc::load_rom(&rom_path);
+        self.ram = vec![0; mbc::ram_size(&self.rom)];
+        self.battery = mbc::has_battery(&self.rom);
+        self.save_path = mbc::save_path(&rom_path);
+        if self.battery {
+            mbc::load_save_file(&self.save_path, &mut self.ram);
+        }
+        self.multicart = is_multicart(&self.rom);
+        if self.multicart {
+            println!("The game uses an MBC1M multicart, so it's supported. Continuing...");
+        } else {
+            println!("The game uses MBC1, so it's supported. Continuing...");
+        }
+    }
+
+    fn save_ram(&mut self) {
+        if self.battery && self.ram_dirty {
+            mbc::write_save_file(&self.save_path, &self.ram);
+            self.ram_dirty = false;
+        }
+    }
+}
+
+impl MBC1 {
+    pub fn new() -> MBC1 {
+        MBC1 {
+            rom: Vec::new(),
+            ram: Vec::new(),
+            battery: false,
+            save_path: PathBuf::new(),
+            ram_dirty: false,
+            ram_enabled: false,
+            rom_bank: 1,
+            upper_bank: 0,
+            advanced_banking: false,
+            multicart: false,
+        }
+    }
+
+    fn lower_bank_bits(&self) -> usize {
+        if self.multicart {
+            return (self.rom_bank & 0x0F) as usize;
+        }
+        return self.rom_bank as usize;
+    }
+
+    fn upper_bank_bits(&self) -> usize {
+        if self.multicart {
+            return (self.upper_bank as usize) << 4;
+        }
+        return (self.upper_bank as usize) << 5;
+    }
+
+    fn rom_offset(&self, bank: usize, address: u16) -> usize {
+        // Bank numbers bigger than the ROM wrap around, as the unused pins aren't connected
+        let bank = bank % (self.rom.len() / ROM_BANK_SIZE);
+        return bank * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1));
+    }
+
+    fn ram_offset(&self, address: u16) -> usize {
+        let bank = if self.advanced_banking {
+            self.upper_bank as usize
+        } else {
+            0
+        };
+        return (bank * RAM_BANK_SIZE + (address as usize - EXTERNAL_RAM_START)) % self.ram.len();
+    }
+}
+
+// MBC1M carts are 8 Mbit and have a second Nintendo logo at the start of game 1 (bank 0x10)
+fn is_multicart(rom: &[u8]) -> bool {
+    if rom.len() != 0x100000 {
+        return false;
+    }
+    let logo = &rom[0x0104..0x0134];
+    let second_logo_start = 0x10 * ROM_BANK_SIZE + 0x0104;
+    return &rom[second_logo_start..second_logo_start + logo.len()] == logo;
+}
@@ -0,0 +1,102 @@
+use crate::mbc;
+use crate::mbc::*;
+use crate::memory_map::*;
+use std::path::PathBuf;
+
+// 512 half-bytes of built-in RAM, echoed through the whole 0xA000-0xBFFF area
+const MBC2_RAM_SIZE: usize = 0x200;
+
+pub struct MBC2 {
+    rom: Vec<u8>,
+    ram: [u8; MBC2_RAM_SIZE],
+    battery: bool,
+    save_path: PathBuf,
+    ram_dirty: bool,
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl mbc::MBC for MBC2 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address as usize {
+            BANK0_START..=BANK0_END => return self.rom[address as usize],
+            BANKN_START..=BANKN_END => {
+                let bank = self.rom_bank as usize % (self.rom.len() / ROM_BANK_SIZE);
+                return self.rom[bank * ROM_BANK_SIZE + (address as usize - BANKN_START)];
+            }
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+                // Only the lower 4 bits exist, the upper ones read as 1
+                return self.ram[address as usize & (MBC2_RAM_SIZE - 1)] | 0xF0;
+            }
+            _ => return 0xFF,
+        }
+    }
+    fn read_word(&self, address: u16) -> u16 {
+        return (self.read_byte(address) as u16) | ((self.read_byte(address + 1) as u16) << 8);
+    }
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address as usize {
+            // Bit 8 of the address selects between RAM Enable and ROM Bank Number
+            0x0000..=0x3FFF => {
+                if address & 0x0100 == 0 {
+                    let was_enabled = self.ram_enabled;
+                    self.ram_enabled = value & 0x0F == 0x0A;
+                    // Games disable the RAM once they are done saving
+                    if was_enabled && !self.ram_enabled {
+                        self.save_ram();
+                    }
+                } else {
+                    self.rom_bank = value & 0x0F;
+                    if self.rom_bank == 0 {
+                        self.rom_bank = 1;
+                    }
+                }
+            }
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => {
+                if self.ram_enabled {
+                    self.ram[address as usize & (MBC2_RAM_SIZE - 1)] = value & 0x0F;
+                    self.ram_dirty = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    fn write_word(&mut self, address: u16, value: u16) {
+        self.write_byte(address, value as u8);
+        self.write_byte(address + 1, (value >> 8) as u8);
+    }
+
+    fn open_rom(&mut self, rom_path: PathBuf) {
+        self.rom = mbc::load_rom(&rom_path);
+        self.battery = mbc::has_battery(&self.rom);
+        self.save_path = mbc::save_path(&rom_path);
+        if self.battery {
+            mbc::load_save_file(&self.save_path, &mut self.ram);
+        }
+        println!("The game uses MBC2, so it's supported. Continuing...");
+    }
+
+    fn save_ram(&mut self) {
+        if self.battery && self.ram_dirty {
+            mbc::write_save_file(&self.save_path, &self.ram);
+            self.ram_dirty = false;
+        }
+    }
+}
+
+impl MBC2 {
+    pub fn new() -> MBC2 {
+        MBC2 {
+            rom: Vec::new(),
+            ram: [0; MBC2_RAM_SIZE],
+            battery: false,
+            save_path: PathBuf::new(),
+            ram_dirty: false,
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::mbc;
+use crate::mbc::*;
+use crate::memory_map::*;
+use crate::rtc::*;
+use std::path::PathBuf;
+
+pub struct MBC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rtc: Option<RealTimeClock>,
+    battery: bool,
+    save_path: PathBuf,
+    // The RTC registers are part of the save file, so writing them also marks it as changed
+    ram_dirty: bool,
+    // Enables both the external RAM and the RTC registers
+    ram_enabled: bool,
+    rom_bank: u8,
+    // 0x00-0x03 select a RAM bank, 0x08-0x0C select an RTC register
+    ram_bank: u8,
+}
+
+impl mbc::MBC for MBC3 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address as usize {
+            BANK0_START..=BANK0_END => return self.rom[address as usize],
+            BANKN_START..=BANKN_END => {
+                let bank = self.rom_bank as usize % (self.rom.len() / ROM_BANK_SIZE);
+                return self.rom[bank * ROM_BANK_SIZE + (address as usize - BANKN_START)];
+            }
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+                match self.ram_bank {
+                    0x00..=0x03 if !self.ram.is_empty() => {
+                        return self.ram[self.ram_offset(address)];
+                    }
+                    RTC_SECONDS..=RTC_DAY_HIGH => match &self.rtc {
+                        Some(rtc) => return rtc.read_register(self.ram_bank),
+                        None => return 0xFF,
+                    },
+                    _ => return 0xFF,
+                }
+            }
+            _ => return 0xFF,
+        }
+    }
+    fn read_word(&self, address: u16) -> u16 {
+        return (self.read_byte(address) as u16) | ((self.read_byte(address + 1) as u16) << 8);
+    }
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address as usize {
+            // RAM and Timer Enable
+            0x0000..=0x1FFF => {
+                let was_enabled = self.ram_enabled;
+                self.ram_enabled = value & 0x0F == 0x0A;
+                // Games disable the RAM once they are done saving
+                if was_enabled && !self.ram_enabled {
+                    self.save_ram();
+                }
+            }
+            // ROM Bank Number, 0 is treated as 1
+            0x2000..=0x3FFF => {
+                self.rom_bank = value & 0x7F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            // RAM Bank Number or RTC Register Select
+            0x4000..=0x5FFF => self.ram_bank = value,
+            // Latch Clock Data
+            0x6000..=0x7FFF => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(value);
+                }
+            }
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => {
+                if !self.ram_enabled {
+                    return;
+                }
+                match self.ram_bank {
+                    0x00..=0x03 if !self.ram.is_empty() => {
+                        let offset = self.ram_offset(address);
+                        self.ram[offset] = value;
+                        self.ram_dirty = true;
+                    }
+                    RTC_SECONDS..=RTC_DAY_HIGH => {
+                        if let Some(rtc) = &mut self.rtc {
+                            rtc.write_register(self.ram_bank, value);
+                            self.ram_dirty = true;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    fn write_word(&mut self, address: u16, value: u16) {
+        self.write_byte(address, value as u8);
+        self.write_byte(address + 1, (value >> 8) as u8);
+    }
+
+    fn open_rom(&mut self, rom_path: PathBuf) {
+        self.rom = mbc::load_rom(&rom_path);
+        self.ram = vec![0; mbc::ram_size(&self.rom)];
+        // MBC3+TIMER+BATTERY, MBC3+TIMER+RAM+BATTERY
+        if self.rom[HEADER_CARTRIDGE_TYPE] == 0x0F || self.rom[HEADER_CARTRIDGE_TYPE] == 0x10 {
+            self.rtc = Some(RealTimeClock::new());
+        }
+        self.battery = mbc::has_battery(&self.rom);
+        self.save_path = mbc::save_path(&rom_path);
+        if self.battery {
+            // The RTC is stored right after the RAM
+            let trailer = mbc::load_save_file(&self.save_path, &mut self.ram);
+            if let Some(rtc) = &mut self.rtc {
+                if !trailer.is_empty() {
+                    rtc.load_save_bytes(&trailer);
+                }
+            }
+        }
+        println!("The game uses MBC3, so it's supported. Continuing...");
+    }
+
+    fn save_ram(&mut self) {
+        if !self.battery || !self.ram_dirty {
+            return;
+        }
+        let mut data = self.ram.clone();
+        if let Some(rtc) = &mut self.rtc {
+            data.extend(rtc.to_save_bytes());
+        }
+        mbc::write_save_file(&self.save_path, &data);
+        self.ram_dirty = false;
+    }
+}
+
+impl MBC3 {
+    pub fn new() -> MBC3 {
+        MBC3 {
+            rom: Vec::new(),
+            ram: Vec::new(),
+            rtc: None,
+            battery: false,
+            save_path: PathBuf::new(),
+            ram_dirty: false,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> usize {
+        let bank = (self.ram_bank & 0x03) as usize;
+        return (bank * RAM_BANK_SIZE + (address as usize - EXTERNAL_RAM_START)) % self.ram.len();
+    }
+}
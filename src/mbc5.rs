@@ -0,0 +1,136 @@
+use crate::mbc;
+use crate::mbc::*;
+use crate::memory_map::*;
+use std::path::PathBuf;
+
+// On rumble carts this bit of the RAM bank register drives the motor instead
+const RUMBLE_BIT: u8 = 0b0000_1000;
+
+pub struct MBC5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+    save_path: PathBuf,
+    ram_dirty: bool,
+    ram_enabled: bool,
+    // 9 bits, unlike the other MBCs bank 0 can be mapped to 0x4000-0x7FFF
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble_active: bool,
+}
+
+impl mbc::MBC for MBC5 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address as usize {
+            BANK0_START..=BANK0_END => return self.rom[address as usize],
+            BANKN_START..=BANKN_END => {
+                let bank = self.rom_bank as usize % (self.rom.len() / ROM_BANK_SIZE);
+                return self.rom[bank * ROM_BANK_SIZE + (address as usize - BANKN_START)];
+            }
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => {
+                if !self.ram_enabled || self.ram.is_empty() {
+                    return 0xFF;
+                }
+                return self.ram[self.ram_offset(address)];
+            }
+            _ => return 0xFF,
+        }
+    }
+    fn read_word(&self, address: u16) -> u16 {
+        return (self.read_byte(address) as u16) | ((self.read_byte(address + 1) as u16) << 8);
+    }
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address as usize {
+            // RAM Enable, only 0x0A enables it on MBC5
+            0x0000..=0x1FFF => {
+                let was_enabled = self.ram_enabled;
+                self.ram_enabled = value == 0x0A;
+                // Games disable the RAM once they are done saving
+                if was_enabled && !self.ram_enabled {
+                    self.save_ram();
+                }
+            }
+            // Lower 8 bits of the ROM Bank Number
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            // 9th bit of the ROM Bank Number
+            0x3000..=0x3FFF => {
+                self.rom_bank = (self.rom_bank & 0x0FF) | (((value & 0x01) as u16) << 8)
+            }
+            // RAM Bank Number
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble_active = value & RUMBLE_BIT == RUMBLE_BIT;
+                    self.ram_bank = value & 0x07;
+                } else {
+                    self.ram_bank = value & 0x0F;
+                }
+            }
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => {
+                if self.ram_enabled && !self.ram.is_empty() {
+                    let offset = self.ram_offset(address);
+                    self.ram[offset] = value;
+                    self.ram_dirty = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    fn write_word(&mut self, address: u16, value: u16) {
+        self.write_byte(address, value as u8);
+        self.write_byte(address + 1, (value >> 8) as u8);
+    }
+
+    fn open_rom(&mut self, rom_path: PathBuf) {
+        self.rom = mbc::load_rom(&rom_path);
+        self.ram = vec![0; mbc::ram_size(&self.rom)];
+        self.battery = mbc::has_battery(&self.rom);
+        self.save_path = mbc::save_path(&rom_path);
+        if self.battery {
+            mbc::load_save_file(&self.save_path, &mut self.ram);
+        }
+        // MBC5+RUMBLE, MBC5+RUMBLE+RAM, MBC5+RUMBLE+RAM+BATTERY
+        self.has_rumble = match self.rom[HEADER_CARTRIDGE_TYPE] {
+            0x1C..=0x1E => true,
+            _ => false,
+        };
+        if self.has_rumble {
+            println!("The game uses MBC5 with rumble, so it's supported. Continuing...");
+        } else {
+            println!("The game uses MBC5, so it's supported. Continuing...");
+        }
+    }
+
+    fn save_ram(&mut self) {
+        if self.battery && self.ram_dirty {
+            mbc::write_save_file(&self.save_path, &self.ram);
+            self.ram_dirty = false;
+        }
+    }
+
+    fn rumble_active(&self) -> bool {
+        return self.rumble_active;
+    }
+}
+
+impl MBC5 {
+    pub fn new() -> MBC5 {
+        MBC5 {
+            rom: Vec::new(),
+            ram: Vec::new(),
+            battery: false,
+            save_path: PathBuf::new(),
+            ram_dirty: false,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble: false,
+            rumble_active: false,
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> usize {
+        let bank = self.ram_bank as usize;
+        return (bank * RAM_BANK_SIZE + (address as usize - EXTERNAL_RAM_START)) % self.ram.len();
+    }
+}
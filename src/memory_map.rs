@@ -8,6 +8,9 @@ pub const RAM_END: usize = 0xDFFF;
 pub const VRAM_START: usize = 0x8000;
 pub const VRAM_END: usize = 0x9FFF;
 
+pub const EXTERNAL_RAM_START: usize = 0xA000;
+pub const EXTERNAL_RAM_END: usize = 0xBFFF;
+
 pub const MIRROR_START: usize = 0xE000;
 pub const MIRROR_END: usize = 0xFDFF;
 
@@ -17,6 +20,9 @@ pub const OAM_END: usize = 0xFE9F;
 pub const UNUSED_AREA_START: usize = 0xFEA0;
 pub const UNUSED_AREA_END: usize = 0xFEFF;
 
+pub const IO_START: usize = 0xFF00;
+pub const IO_END: usize = 0xFF7F;
+
 pub const HRAM_START: usize = 0xFF80;
 pub const HRAM_END: usize = 0xFFFE;
 
@@ -41,10 +47,10 @@ pub const NR11: usize = 0xFF11;
 pub const NR12: usize = 0xFF12;
 pub const NR13: usize = 0xFF13;
 pub const NR14: usize = 0xFF14;
-pub const NR16: usize = 0xFF21;
-pub const NR17: usize = 0xFF22;
-pub const NR18: usize = 0xFF23;
-pub const NR19: usize = 0xFF24;
+pub const NR21: usize = 0xFF16;
+pub const NR22: usize = 0xFF17;
+pub const NR23: usize = 0xFF18;
+pub const NR24: usize = 0xFF19;
 pub const NR30: usize = 0xFF1A;
 pub const NR31: usize = 0xFF1B;
 pub const NR32: usize = 0xFF1C;
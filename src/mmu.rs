@@ -1,4 +1,5 @@
-use crate::mbc::MBC;
+use crate::mbc;
+use crate::mbc::{MBCType, MBC};
 use crate::memory_map::*;
 use std::path::PathBuf;
 
@@ -6,12 +7,18 @@ const RAM_SIZE: usize = (RAM_END - RAM_START) + 1;
 
 const HRAM_SIZE: usize = (HRAM_END - HRAM_START) + 1;
 
+const DMA_LENGTH: u16 = (OAM_END - OAM_START + 1) as u16;
+
 pub struct MMU {
     mbc: Box<dyn MBC>,
     ram: [u8; RAM_SIZE],
     high_ram: [u8; HRAM_SIZE],
-    pub dma_transfer: bool,
-    pub dma_address: u16,
+    dma_register: u8,
+    dma_transfer: bool,
+    dma_address: u16,
+    dma_index: u16,
+    // Source of a requested transfer that hasn't started yet
+    dma_pending: Option<u16>,
 }
 
 impl MMU {
@@ -20,20 +27,24 @@ impl MMU {
             mbc: Box::new(crate::mbc0::MBC0::new()),
             ram: [0; RAM_SIZE as usize],
             high_ram: [0; HRAM_SIZE as usize],
+            dma_register: 0,
             dma_transfer: false,
             dma_address: 0,
+            dma_index: 0,
+            dma_pending: None,
         }
     }
     pub fn read_byte(&self, address: u16) -> u8 {
         match address as usize {
             ROM_START..=ROM_END => return self.mbc.read_byte(address),
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => return self.mbc.read_byte(address),
             RAM_START..=RAM_END => return self.ram[(address as usize - RAM_START)],
+            // Echo RAM mirrors $C000-$DDFF
+            MIRROR_START..=MIRROR_END => return self.ram[address as usize - MIRROR_START],
             HRAM_START..=HRAM_END => return self.high_ram[(address as usize - HRAM_START)],
 
-            _ => {
-                println!("Invalid Read! This memory section is not supported (yet?). The location was {:#6X}", address);
-                return 0xFF;
-            }
+            // The CPU decodes the rest of the address space
+            _ => return 0xFF,
         }
     }
     pub fn read_word(&self, address: u16) -> u16 {
@@ -42,11 +53,14 @@ impl MMU {
     pub fn write_byte(&mut self, address: u16, value: u8) {
         match address as usize {
             ROM_START..=ROM_END => return self.mbc.write_byte(address, value),
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => self.mbc.write_byte(address, value),
             RAM_START..=RAM_END => self.ram[(address as usize - RAM_START)] = value,
+            // Echo RAM mirrors $C000-$DDFF
+            MIRROR_START..=MIRROR_END => self.ram[address as usize - MIRROR_START] = value,
             HRAM_START..=HRAM_END => self.high_ram[(address as usize - HRAM_START)] = value,
 
-            _ => println!("Invalid Write! This memory section is not supported (yet?). The location was {:#6X}", address),
-
+            // The CPU decodes the rest of the address space
+            _ => {}
         }
     }
     pub fn write_word(&mut self, address: u16, value: u16) {
@@ -55,11 +69,70 @@ impl MMU {
     }
 
     pub fn open_rom(&mut self, rom_path: PathBuf) {
+        let cartridge_type = mbc::read_cartridge_type(&rom_path);
+        self.swap_mbc(MBCType::from_cartridge_type(cartridge_type));
         self.mbc.open_rom(rom_path);
     }
 
+    pub fn open_gbs(&mut self, gbs_path: PathBuf) {
+        self.mbc = Box::new(crate::gbs::GBS::new());
+        self.mbc.open_rom(gbs_path);
+    }
+
+    pub fn swap_mbc(&mut self, mbc_type: MBCType) {
+        self.mbc = match mbc_type {
+            MBCType::MBC0 => Box::new(crate::mbc0::MBC0::new()),
+            MBCType::MBC1 => Box::new(crate::mbc1::MBC1::new()),
+            MBCType::MBC2 => Box::new(crate::mbc2::MBC2::new()),
+            MBCType::MBC3 => Box::new(crate::mbc3::MBC3::new()),
+            MBCType::MBC5 => Box::new(crate::mbc5::MBC5::new()),
+            MBCType::HuC1 => Box::new(crate::huc1::HuC1::new()),
+        };
+    }
+
+    pub fn save_ram(&mut self) {
+        self.mbc.save_ram();
+    }
+
+    pub fn rumble_active(&self) -> bool {
+        return self.mbc.rumble_active();
+    }
+
+    pub fn dma_register(&self) -> u8 {
+        return self.dma_register;
+    }
+
+    pub fn dma_transfer(&self) -> bool {
+        return self.dma_transfer;
+    }
+
     pub fn start_dma(&mut self, offset: u8) {
-        self.dma_transfer = true;
-        self.dma_address = ((offset as u16) << 8) & 0xFF00;
+        self.dma_register = offset;
+        let mut source = (offset as u16) << 8;
+        // The DMA can't see the IO area, sources above $DF read from the Echo RAM instead
+        if source as usize >= MIRROR_START {
+            source -= (MIRROR_START - RAM_START) as u16;
+        }
+        // The transfer starts one M-cycle after the write, an ongoing one keeps running meanwhile
+        self.dma_pending = Some(source);
+    }
+
+    // Advances the DMA by one M-cycle, returns the source address and the OAM offset to copy
+    pub fn step_dma(&mut self) -> Option<(u16, usize)> {
+        let mut transfer = None;
+        if self.dma_transfer {
+            transfer = Some((self.dma_address + self.dma_index, self.dma_index as usize));
+            self.dma_index += 1;
+            if self.dma_index == DMA_LENGTH {
+                self.dma_transfer = false;
+            }
+        }
+
+        if let Some(source) = self.dma_pending.take() {
+            self.dma_transfer = true;
+            self.dma_address = source;
+            self.dma_index = 0;
+        }
+        return transfer;
     }
 }
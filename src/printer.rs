@@ -0,0 +1,311 @@
+extern crate sfml;
+
+use crate::link_cable::LinkPeer;
+use sfml::graphics::Image;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAGIC_FIRST: u8 = 0x88;
+const MAGIC_SECOND: u8 = 0x33;
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+// Answer to the first byte after the checksum, it tells the Game Boy a printer is connected
+const ALIVE: u8 = 0x81;
+
+const CHECKSUM_ERROR: u8 = 0b0000_0001;
+const PRINTER_BUSY: u8 = 0b0000_0010;
+const IMAGE_DATA_FULL: u8 = 0b0000_0100;
+const UNPROCESSED_DATA: u8 = 0b0000_1000;
+const PACKET_ERROR: u8 = 0b0001_0000;
+
+// The printer RAM holds 9 bands of 2 tile rows
+const IMAGE_BUFFER_SIZE: usize = 0x280 * 9;
+const TILES_PER_ROW: usize = 20;
+const TILE_SIZE: usize = 16;
+const PAPER_WIDTH: usize = TILES_PER_ROW * 8;
+// Each margin step feeds about one tile row of blank paper
+const MARGIN_LINES: usize = 8;
+// Status packets answered as busy after a print, games wait for this to clear
+const PRINT_BUSY_STATUS_PACKETS: u8 = 16;
+// Paper shades from white to black, the exposure setting isn't emulated
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+enum PacketState {
+    MagicFirst,
+    MagicSecond,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+// Game Boy Printer plugged into the link cable, every printed page is saved as a PNG file
+pub struct Printer {
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    busy_status_packets: u8,
+    // Tile data waiting to be printed
+    image: Vec<u8>,
+    // RGBA lines printed since the paper was last cut
+    paper: Vec<u8>,
+    output_folder: PathBuf,
+    pages_saved: u32,
+}
+
+impl Printer {
+    pub fn new(output_folder: PathBuf) -> Printer {
+        Printer {
+            state: PacketState::MagicFirst,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            busy_status_packets: 0,
+            image: Vec::new(),
+            paper: Vec::new(),
+            output_folder,
+            pages_saved: 0,
+        }
+    }
+
+    fn receive_byte(&mut self, byte: u8) {
+        match self.state {
+            PacketState::MagicFirst => {
+                if byte == MAGIC_FIRST {
+                    self.state = PacketState::MagicSecond;
+                }
+            }
+            PacketState::MagicSecond => {
+                self.state = match byte {
+                    MAGIC_SECOND => PacketState::Command,
+                    MAGIC_FIRST => PacketState::MagicSecond,
+                    _ => PacketState::MagicFirst,
+                }
+            }
+            PacketState::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                self.state = PacketState::Compression;
+            }
+            PacketState::Compression => {
+                self.compressed = byte & 0b0000_0001 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.state = PacketState::LengthLow;
+            }
+            PacketState::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.state = PacketState::LengthHigh;
+            }
+            PacketState::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.data.clear();
+                self.state = if self.length == 0 {
+                    PacketState::ChecksumLow
+                } else {
+                    PacketState::Data
+                };
+            }
+            PacketState::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.data.len() == self.length as usize {
+                    self.state = PacketState::ChecksumLow;
+                }
+            }
+            PacketState::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                self.state = PacketState::ChecksumHigh;
+            }
+            PacketState::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                // The status sent at the end of the packet already reflects it
+                self.process_packet();
+                self.state = PacketState::Alive;
+            }
+            PacketState::Alive => self.state = PacketState::Status,
+            PacketState::Status => self.state = PacketState::MagicFirst,
+        }
+    }
+
+    fn process_packet(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !CHECKSUM_ERROR;
+
+        match self.command {
+            INIT => {
+                self.image.clear();
+                self.status = 0;
+                self.busy_status_packets = 0;
+            }
+            DATA => {
+                let data = if self.compressed {
+                    decompress(&self.data)
+                } else {
+                    self.data.clone()
+                };
+                let space_left = IMAGE_BUFFER_SIZE - self.image.len();
+                self.image
+                    .extend_from_slice(&data[..data.len().min(space_left)]);
+                // An empty data packet only marks the end of the image
+                if !self.image.is_empty() {
+                    self.status |= UNPROCESSED_DATA;
+                }
+                if self.image.len() == IMAGE_BUFFER_SIZE {
+                    self.status |= IMAGE_DATA_FULL;
+                }
+            }
+            PRINT => {
+                if self.data.len() < 4 {
+                    self.status |= PACKET_ERROR;
+                    return;
+                }
+                let (sheets, margins, palette) = (self.data[0], self.data[1], self.data[2]);
+                self.print(sheets, margins, palette);
+                self.image.clear();
+                self.status &= !(UNPROCESSED_DATA | IMAGE_DATA_FULL);
+                self.status |= PRINTER_BUSY;
+                self.busy_status_packets = PRINT_BUSY_STATUS_PACKETS;
+            }
+            STATUS => {
+                if self.busy_status_packets > 0 {
+                    self.busy_status_packets -= 1;
+                    if self.busy_status_packets == 0 {
+                        self.status &= !PRINTER_BUSY;
+                    }
+                }
+            }
+            _ => self.status |= PACKET_ERROR,
+        }
+    }
+
+    // The upper nibble of the margins is fed before the image and the lower one after it, which cuts the page
+    fn print(&mut self, sheets: u8, margins: u8, palette: u8) {
+        self.feed_paper((margins >> 4) as usize * MARGIN_LINES);
+        for _ in 0..sheets {
+            self.print_image(palette);
+        }
+        let margin_after = (margins & 0x0F) as usize * MARGIN_LINES;
+        if margin_after > 0 {
+            self.feed_paper(margin_after);
+            self.save_page();
+        }
+    }
+
+    fn print_image(&mut self, palette: u8) {
+        // Games that leave the palette at 0 get the usual one
+        let palette = if palette == 0 { 0b1110_0100 } else { palette };
+        let tile_rows = self.image.len() / (TILES_PER_ROW * TILE_SIZE);
+        for tile_row in 0..tile_rows {
+            for line in 0..8 {
+                for tile in 0..TILES_PER_ROW {
+                    let offset = (tile_row * TILES_PER_ROW + tile) * TILE_SIZE + line * 2;
+                    let (low, high) = (self.image[offset], self.image[offset + 1]);
+                    for bit in (0..8).rev() {
+                        let color = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+                        let shade = SHADES[((palette >> (color * 2)) & 0b11) as usize];
+                        self.paper.extend_from_slice(&[shade, shade, shade, 0xFF]);
+                    }
+                }
+            }
+        }
+    }
+
+    fn feed_paper(&mut self, lines: usize) {
+        let length = self.paper.len() + lines * PAPER_WIDTH * 4;
+        self.paper.resize(length, 0xFF);
+    }
+
+    fn save_page(&mut self) {
+        let height = self.paper.len() / (PAPER_WIDTH * 4);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        self.pages_saved += 1;
+        let path = self
+            .output_folder
+            .join(format!("print-{}-{}.png", timestamp, self.pages_saved));
+        let saved = match Image::create_from_pixels(PAPER_WIDTH as u32, height as u32, &self.paper)
+        {
+            Some(image) => image.save_to_file(&path.to_string_lossy()),
+            None => false,
+        };
+        if saved {
+            println!("Printed {:?}", path);
+        } else {
+            eprintln!("Couldn't save the print to {:?}", path);
+        }
+        self.paper.clear();
+    }
+}
+
+// Paper that was never cut by an after-margin is saved when the printer is unplugged
+impl Drop for Printer {
+    fn drop(&mut self) {
+        if !self.paper.is_empty() {
+            self.save_page();
+        }
+    }
+}
+
+impl LinkPeer for Printer {
+    // The answer is shifted out while the byte comes in, so it can only depend on what came before
+    fn exchange(&mut self, outgoing: u8) -> io::Result<u8> {
+        let response = match self.state {
+            PacketState::Alive => ALIVE,
+            PacketState::Status => self.status,
+            _ => 0x00,
+        };
+        self.receive_byte(outgoing);
+        return Ok(response);
+    }
+
+    // The printer never drives the clock
+    fn poll(&mut self, _cycles: u8, _outgoing: u8) -> io::Result<Option<u8>> {
+        return Ok(None);
+    }
+}
+
+// Runs with the top bit set repeat the next byte (length + 2) times, otherwise (length + 1) bytes are copied
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut index = 0;
+    while index < data.len() {
+        let control = data[index];
+        index += 1;
+        if control & 0b1000_0000 != 0 {
+            let length = (control & 0b0111_1111) as usize + 2;
+            if let Some(&byte) = data.get(index) {
+                output.extend(std::iter::repeat(byte).take(length));
+            }
+            index += 1;
+        } else {
+            let end = (index + control as usize + 1).min(data.len());
+            output.extend_from_slice(&data[index..end]);
+            index = end;
+        }
+    }
+    return output;
+}
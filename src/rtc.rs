@@ -0,0 +1,179 @@
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// RAM bank numbers that select an RTC register instead
+pub const RTC_SECONDS: u8 = 0x08;
+pub const RTC_MINUTES: u8 = 0x09;
+pub const RTC_HOURS: u8 = 0x0A;
+pub const RTC_DAY_LOW: u8 = 0x0B;
+pub const RTC_DAY_HIGH: u8 = 0x0C;
+
+// Size of the RTC trailer appended to the .sav file (the format used by BGB and VBA-M)
+pub const RTC_SAVE_SIZE: usize = 48;
+
+const DAY_HIGH_BIT: u8 = 0b0000_0001;
+const HALT_BIT: u8 = 0b0100_0000;
+const DAY_CARRY_BIT: u8 = 0b1000_0000;
+
+#[derive(Clone, Copy, Default)]
+struct ClockRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day_low: u8,
+    day_high: u8,
+}
+
+pub struct RealTimeClock {
+    clock: ClockRegisters,
+    latched: ClockRegisters,
+    // Last value written to the latch register, latching happens on a 0x00 -> 0x01 write
+    latch_value: u8,
+    // Host time (seconds since the UNIX epoch) the clock was last brought up to date
+    last_update: u64,
+}
+
+impl RealTimeClock {
+    pub fn new() -> RealTimeClock {
+        RealTimeClock {
+            clock: ClockRegisters::default(),
+            latched: ClockRegisters::default(),
+            latch_value: 0xFF,
+            last_update: now(),
+        }
+    }
+
+    pub fn read_register(&self, register: u8) -> u8 {
+        match register {
+            RTC_SECONDS => self.latched.seconds,
+            RTC_MINUTES => self.latched.minutes,
+            RTC_HOURS => self.latched.hours,
+            RTC_DAY_LOW => self.latched.day_low,
+            RTC_DAY_HIGH => self.latched.day_high,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, register: u8, value: u8) {
+        self.update();
+        match register {
+            RTC_SECONDS => self.clock.seconds = value & 0x3F,
+            RTC_MINUTES => self.clock.minutes = value & 0x3F,
+            RTC_HOURS => self.clock.hours = value & 0x1F,
+            RTC_DAY_LOW => self.clock.day_low = value,
+            RTC_DAY_HIGH => self.clock.day_high = value & (DAY_HIGH_BIT | HALT_BIT | DAY_CARRY_BIT),
+            _ => {}
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_value == 0x00 && value == 0x01 {
+            self.update();
+            self.latched = self.clock;
+        }
+        self.latch_value = value;
+    }
+
+    // Advances the clock by the host time elapsed since the last update
+    fn update(&mut self) {
+        let now = now();
+        let elapsed = now.saturating_sub(self.last_update);
+        self.last_update = now;
+        if self.clock.day_high & HALT_BIT == HALT_BIT || elapsed == 0 {
+            return;
+        }
+
+        let mut total = elapsed + self.clock.seconds as u64;
+        self.clock.seconds = (total % 60) as u8;
+        total = total / 60 + self.clock.minutes as u64;
+        self.clock.minutes = (total % 60) as u8;
+        total = total / 60 + self.clock.hours as u64;
+        self.clock.hours = (total % 24) as u8;
+        total = total / 24 + self.days() as u64;
+
+        // The day counter is 9 bits wide, the carry stays set until the game clears it
+        if total > 0x1FF {
+            self.clock.day_high |= DAY_CARRY_BIT;
+        }
+        self.set_days((total & 0x1FF) as u16);
+    }
+
+    fn days(&self) -> u16 {
+        return ((self.clock.day_high & DAY_HIGH_BIT) as u16) << 8 | self.clock.day_low as u16;
+    }
+
+    fn set_days(&mut self, days: u16) {
+        self.clock.day_low = days as u8;
+        self.clock.day_high =
+            (self.clock.day_high & !DAY_HIGH_BIT) | ((days >> 8) as u8 & DAY_HIGH_BIT);
+    }
+
+    // Five 32-bit clock registers, five 32-bit latched registers and a 64-bit timestamp, all little endian
+    pub fn to_save_bytes(&mut self) -> Vec<u8> {
+        self.update();
+        let mut bytes: Vec<u8> = Vec::with_capacity(RTC_SAVE_SIZE);
+        for registers in [self.clock, self.latched].iter() {
+            for value in registers.as_array().iter() {
+                bytes.extend_from_slice(&(*value as u32).to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&self.last_update.to_le_bytes());
+        return bytes;
+    }
+
+    pub fn load_save_bytes(&mut self, bytes: &[u8]) {
+        if bytes.len() < RTC_SAVE_SIZE {
+            eprintln!("The RTC data in the save file is incomplete, starting a new clock");
+            return;
+        }
+        let register = |index: usize| -> u8 {
+            return u32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().unwrap()) as u8;
+        };
+        self.clock = ClockRegisters::from_array([
+            register(0),
+            register(1),
+            register(2),
+            register(3),
+            register(4),
+        ]);
+        self.latched = ClockRegisters::from_array([
+            register(5),
+            register(6),
+            register(7),
+            register(8),
+            register(9),
+        ]);
+        self.last_update = u64::from_le_bytes(bytes[40..48].try_into().unwrap());
+        // Catch up with the time that passed while the emulator was closed
+        self.update();
+    }
+}
+
+impl ClockRegisters {
+    fn as_array(&self) -> [u8; 5] {
+        return [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.day_low,
+            self.day_high,
+        ];
+    }
+
+    fn from_array(values: [u8; 5]) -> ClockRegisters {
+        ClockRegisters {
+            seconds: values[0],
+            minutes: values[1],
+            hours: values[2],
+            day_low: values[3],
+            day_high: values[4],
+        }
+    }
+}
+
+fn now() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+}
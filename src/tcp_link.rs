@@ -0,0 +1,168 @@
+use crate::link_cable::LinkPeer;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+// Both emulators meet at a barrier once per frame, so neither can run ahead of the other
+const SYNC_DOTS: u32 = 70224;
+// Incoming transfers are looked for once per scanline, polling on every instruction is too slow
+const POLL_DOTS: u32 = 456;
+
+const TRANSFER: u8 = 0x01;
+const REPLY: u8 = 0x02;
+const SYNC: u8 = 0x03;
+
+enum Message {
+    // A byte clocked out by the side using its internal clock
+    Transfer(u8),
+    // The byte shifted back in answer to a transfer
+    Reply(u8),
+    // The sender reached the barrier with this number
+    Sync(u32),
+}
+
+// Link cable to another gmbr process, one side listens and the other connects
+pub struct TcpLink {
+    stream: TcpStream,
+    received: Vec<u8>,
+    sync_dots: u32,
+    poll_dots: u32,
+    syncs_sent: u32,
+    syncs_received: u32,
+}
+
+impl TcpLink {
+    pub fn listen(address: &str) -> io::Result<TcpLink> {
+        let listener = TcpListener::bind(address)?;
+        println!("Waiting for the other Game Boy on {}...", address);
+        let (stream, peer_address) = listener.accept()?;
+        println!("Linked with {}", peer_address);
+        return TcpLink::new(stream);
+    }
+
+    pub fn connect(address: &str) -> io::Result<TcpLink> {
+        let stream = TcpStream::connect(address)?;
+        println!("Linked with {}", address);
+        return TcpLink::new(stream);
+    }
+
+    fn new(stream: TcpStream) -> io::Result<TcpLink> {
+        // Every transfer waits for an answer, so nothing can be held back to be batched
+        stream.set_nodelay(true)?;
+        return Ok(TcpLink {
+            stream,
+            received: Vec::new(),
+            sync_dots: 0,
+            poll_dots: 0,
+            syncs_sent: 0,
+            syncs_received: 0,
+        });
+    }
+
+    fn send(&mut self, message: Message) -> io::Result<()> {
+        match message {
+            Message::Transfer(byte) => self.stream.write_all(&[TRANSFER, byte])?,
+            Message::Reply(byte) => self.stream.write_all(&[REPLY, byte])?,
+            Message::Sync(count) => {
+                self.stream.write_all(&[SYNC])?;
+                self.stream.write_all(&count.to_le_bytes())?;
+            }
+        }
+        return Ok(());
+    }
+
+    // Returns the next message, without waiting for it unless `wait` is set
+    fn receive(&mut self, wait: bool) -> io::Result<Option<Message>> {
+        loop {
+            if let Some(message) = self.parse_message()? {
+                return Ok(Some(message));
+            }
+            self.stream.set_nonblocking(!wait)?;
+            let mut chunk = [0; 64];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the other Game Boy closed the connection",
+                    ))
+                }
+                Ok(length) => self.received.extend_from_slice(&chunk[..length]),
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn parse_message(&mut self) -> io::Result<Option<Message>> {
+        let length = match self.received.first() {
+            Some(&TRANSFER) | Some(&REPLY) => 2,
+            Some(&SYNC) => 5,
+            Some(tag) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown message {:#04X}", tag),
+                ))
+            }
+            None => return Ok(None),
+        };
+        if self.received.len() < length {
+            return Ok(None);
+        }
+        let bytes: Vec<u8> = self.received.drain(..length).collect();
+        match bytes[0] {
+            TRANSFER => return Ok(Some(Message::Transfer(bytes[1]))),
+            REPLY => return Ok(Some(Message::Reply(bytes[1]))),
+            _ => {
+                let count = [bytes[1], bytes[2], bytes[3], bytes[4]];
+                return Ok(Some(Message::Sync(u32::from_le_bytes(count))));
+            }
+        }
+    }
+}
+
+impl LinkPeer for TcpLink {
+    // The transfer is answered right away, only the local clock decides how long the shifting takes
+    fn exchange(&mut self, outgoing: u8) -> io::Result<u8> {
+        self.send(Message::Transfer(outgoing))?;
+        loop {
+            match self.receive(true)? {
+                Some(Message::Reply(byte)) => return Ok(byte),
+                // Both sides started a transfer with their internal clock, answer so neither hangs
+                Some(Message::Transfer(_)) => self.send(Message::Reply(outgoing))?,
+                Some(Message::Sync(count)) => self.syncs_received = count,
+                None => {}
+            }
+        }
+    }
+
+    fn poll(&mut self, cycles: u8, outgoing: u8) -> io::Result<Option<u8>> {
+        self.sync_dots += cycles as u32;
+        self.poll_dots += cycles as u32;
+
+        if self.sync_dots >= SYNC_DOTS {
+            self.sync_dots -= SYNC_DOTS;
+            self.syncs_sent += 1;
+            self.send(Message::Sync(self.syncs_sent))?;
+        }
+        // Transfers keep being answered while waiting, the other side may be blocked on one
+        let mut wait = self.syncs_received < self.syncs_sent;
+        if wait || self.poll_dots >= POLL_DOTS {
+            self.poll_dots = 0;
+            loop {
+                match self.receive(wait)? {
+                    // One byte per poll, a later transfer stays buffered for the next one
+                    Some(Message::Transfer(byte)) => {
+                        self.send(Message::Reply(outgoing))?;
+                        return Ok(Some(byte));
+                    }
+                    Some(Message::Sync(count)) => self.syncs_received = count,
+                    // A late answer to a transfer both sides started at once
+                    Some(Message::Reply(_)) => {}
+                    None => break,
+                }
+                wait = self.syncs_received < self.syncs_sent;
+            }
+        }
+        return Ok(None);
+    }
+}
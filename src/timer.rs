@@ -1,48 +1,120 @@
 pub struct Timer {
-    div: u8,
+    // DIV is the upper byte of this internal counter, which increments every T-cycle
+    divider: u16,
     tima: u8,
     tma: u8,
     tac: u8,
-    timer_interrupt_req: bool,
-    cycles_passed: u16,
-    _div: u16,
+    pub timer_interrupt_req: bool,
+    // TIMA overflowed on the last M-cycle and reads 0 until it gets reloaded
+    overflow_pending: bool,
+    // TIMA was reloaded from TMA on the last M-cycle
+    reloading: bool,
 }
 
 impl Timer {
     pub fn new() -> Timer {
         Timer {
-            div: 0,
+            divider: 0,
             tima: 0,
             tma: 0,
             tac: 0,
             timer_interrupt_req: false,
-            cycles_passed: 0,
-            _div: 0,
+            overflow_pending: false,
+            reloading: false,
         }
     }
 
     pub fn update_timer(&mut self, cycles: u16) {
-        self._div += cycles;
+        // The timer works in M-cycles, 4 T-cycles each
+        for _ in 0..(cycles / 4) {
+            self.tick();
+        }
+    }
+
+    fn tick(&mut self) {
+        self.reloading = false;
+        if self.overflow_pending {
+            self.overflow_pending = false;
+            self.tima = self.tma;
+            self.timer_interrupt_req = true;
+            self.reloading = true;
+        }
+
+        let old_signal = self.timer_signal();
+        self.divider = self.divider.wrapping_add(4);
+        self.check_falling_edge(old_signal);
+    }
+
+    // TIMA is clocked by the falling edge of the selected divider bit ANDed with the enable bit
+    fn timer_signal(&self) -> bool {
+        let bit = match self.tac & 0b11 {
+            0b00 => 9, // 4096 Hz
+            0b01 => 3, // 262144 Hz
+            0b10 => 5, // 65536 Hz
+            _ => 7,    // 16384 Hz
+        };
+        return self.tac & 0b100 == 0b100 && (self.divider >> bit) & 0b1 == 0b1;
+    }
+
+    fn check_falling_edge(&mut self, old_signal: bool) {
+        if old_signal && !self.timer_signal() {
+            self.increment_tima();
+        }
+    }
 
-        if self._div >= 256 {
-            self._div = 0;
-            self.div.wrapping_add(1);
+    fn increment_tima(&mut self) {
+        let (value, overflow) = self.tima.overflowing_add(1);
+        self.tima = value;
+        if overflow {
+            self.overflow_pending = true;
         }
     }
 
+    pub fn tima(&self) -> u8 {
+        return self.tima;
+    }
+
+    pub fn set_tima(&mut self, value: u8) {
+        // Writes during the reload cycle are ignored
+        if self.reloading {
+            return;
+        }
+        // Writing during the overflow delay cancels the reload and the interrupt
+        self.overflow_pending = false;
+        self.tima = value;
+    }
+
     pub fn tma(&self) -> u8 {
         return self.tma;
     }
 
     pub fn set_tma(&mut self, value: u8) {
         self.tma = value;
+        // TMA written during the reload cycle also ends up in TIMA
+        if self.reloading {
+            self.tima = value;
+        }
+    }
+
+    pub fn tac(&self) -> u8 {
+        return self.tac | 0b1111_1000;
+    }
+
+    pub fn set_tac(&mut self, value: u8) {
+        let old_signal = self.timer_signal();
+        self.tac = value & 0b0000_0111;
+        // Disabling the timer or changing the frequency can produce a falling edge
+        self.check_falling_edge(old_signal);
     }
 
-    pub fn div(&mut self) -> u8 {
-        return self.div;
+    pub fn div(&self) -> u8 {
+        return (self.divider >> 8) as u8;
     }
 
-    pub fn set_div(&mut self, value: u8) {
-        self.div = 0;
+    pub fn set_div(&mut self, _value: u8) {
+        // Any write resets the whole internal counter, which can produce a falling edge
+        let old_signal = self.timer_signal();
+        self.divider = 0;
+        self.check_falling_edge(old_signal);
     }
 }